@@ -0,0 +1,72 @@
+#![feature(type_alias_impl_trait)]
+extern crate real_async_trait;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use real_async_trait::real_async_trait;
+
+pub type Errno = usize;
+pub const ENOENT: usize = 1;
+
+#[real_async_trait(interceptor)]
+pub trait AsyncScheme {
+    #[real_async_trait(Send)]
+    async fn open<'a>(&'a self, path: &'a [u8]) -> Result<usize, Errno>;
+    #[real_async_trait(Send)]
+    async fn write<'a>(&'a mut self, fd: usize, num: u64) -> Result<(), Errno>;
+}
+
+struct NumberScheme {
+    handles: Mutex<BTreeMap<usize, u64>>,
+}
+
+#[real_async_trait]
+impl AsyncScheme for NumberScheme {
+    async fn open<'a>(&'a self, path: &'a [u8]) -> Result<usize, Errno> {
+        let path_str = str::from_utf8(path).or(Err(ENOENT))?;
+        let num = path_str.parse::<usize>().or(Err(ENOENT))?;
+        self.handles.lock().unwrap().insert(num, 0);
+        Ok(num)
+    }
+    async fn write<'a>(&'a mut self, fd: usize, num: u64) -> Result<(), Errno> {
+        let mut handles = self.handles.lock().unwrap();
+        *handles.get_mut(&fd).ok_or(ENOENT)? = num;
+        Ok(())
+    }
+}
+
+struct AuditLog;
+
+impl InterceptedHooks for AuditLog {
+    fn before(&self, method: &'static str, args: &[&dyn fmt::Debug]) {
+        println!("Calling `{}` with {:?}", method, args);
+    }
+    fn after(&self, method: &'static str, output: &dyn fmt::Debug) {
+        println!("`{}` returned {:?}", method, output);
+    }
+}
+
+#[async_std::main]
+async fn main() -> Result<(), Errno> {
+    let numberscheme = NumberScheme {
+        handles: Mutex::new(BTreeMap::new()),
+    };
+    let scheme = Arc::new(Intercepted::new(numberscheme, AuditLog));
+
+    // The futures of the wrapper are as `Send` as those of the scheme it wraps.
+    let spawned_scheme = Arc::clone(&scheme);
+    let fd = async_std::task::spawn(async move { spawned_scheme.open(b"1337").await }).await?;
+
+    let mut scheme = Arc::try_unwrap(scheme).ok().expect("the spawned task is done");
+    scheme.write(fd, 420).await?;
+    println!(
+        "Number container `1337` (fd {}) holds {}",
+        fd,
+        scheme.inner.handles.lock().unwrap()[&fd]
+    );
+
+    Ok(())
+}
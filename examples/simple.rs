@@ -1,5 +1,4 @@
-#![allow(incomplete_features)]
-#![feature(generic_associated_types, type_alias_impl_trait)]
+#![feature(type_alias_impl_trait)]
 extern crate real_async_trait;
 
 use std::collections::BTreeMap;
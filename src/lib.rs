@@ -9,11 +9,11 @@
 //! [docsrs]: https://docs.rs/real-async-trait/badge.svg
 //!
 //! This crate provides a producedural macro that works around the current limitation of not being
-//! able to put `async fn`s in a trait, _without type erasure_, by using experimental
-//! nightly-features, namely [generic associated types
+//! able to put `async fn`s in a trait, _without type erasure_, by using [generic associated types
 //! (GATs)](https://github.com/rust-lang/rfcs/blob/master/text/1598-generic_associated_types.md)
-//! and [existential
+//! and the experimental nightly-feature [existential
 //! types](https://github.com/rust-lang/rfcs/blob/master/text/2515-type_alias_impl_trait.md).
+//! Crates that contain impls must therefore enable `#![feature(type_alias_impl_trait)]`.
 //!
 //! ## Caveats
 //!
@@ -21,11 +21,15 @@
 //! traits, there are a few caveats to this (non-exhaustive):
 //!
 //! * at the moment, all references used in the async fn, must have their lifetimes be explicitly
-//! specified, either from the top-level of the trait, or in the function declaration;
+//!   specified, either from the top-level of the trait, or in the function declaration. The same
+//!   goes for `'_`, which is rejected, and for the lifetime parameters of types such as
+//!   `Cow<'a, str>`, which the compiler reports at the impl;
 //! * there can only be a single lifetime in use simultaneously. I have no idea why, but it could
-//! be due to buggy interaction between existential types and generic associated types;
-//! * since GATs are an "incomplete" feature in rust, it may not be sound or just not compile
-//! correctly or at all. __Don't use this in production code!__
+//!   be due to buggy interaction between existential types and generic associated types;
+//! * since existential types are an unstable feature in rust, the generated code may stop
+//!   compiling correctly or at all with newer nightlies. For instance, current nightlies crash when
+//!   a public trait is implemented for a public type inside a private module, so such impls have
+//!   to live in a publicly reachable module. __Don't use this in production code!__
 //!
 //! ## Example
 //! ```ignore
@@ -82,8 +86,302 @@
 //! # }
 //!
 //! ```
+//! ## Provided methods
+//!
+//! Async methods with a default body cannot use a generic associated type, since the default body
+//! has no way of knowing which type an implementor picked. Their futures are therefore boxed, and
+//! the trait method returns `Pin<Box<dyn Future<Output = T> + 'a>>` instead. Implementations that
+//! override such a method return the same type, without having to be told so:
+//!
+//! ```ignore
+//! #[real_async_trait]
+//! pub trait Pinger {
+//!     async fn ping<'a>(&'a self) -> bool {
+//!         true
+//!     }
+//! }
+//!
+//! #[real_async_trait]
+//! impl Pinger for MyPinger {
+//!     async fn ping<'a>(&'a self) -> bool {
+//!         false
+//!     }
+//! }
+//! ```
+//!
+//! Required methods can be boxed in the same way by marking them with `#[real_async_trait(boxed)]`
+//! in the trait. This is necessary for recursive methods, such as tree walkers,
+//! since their futures would otherwise have to contain themselves. The other methods of the trait
+//! are unaffected, and `Send` is honored for boxed futures as well:
+//!
+//! ```ignore
+//! #[real_async_trait]
+//! pub trait Tree {
+//!     #[real_async_trait(boxed, Send)]
+//!     async fn sum<'a>(&'a self) -> u32;
+//! }
+//! ```
+//!
+//! ## Returning `impl Trait`
+//!
+//! Trait methods that return `impl Trait` rather than being async are lowered in the same way, with
+//! the bounds of the generated associated type being the ones from the signature:
+//!
+//! ```ignore
+//! #[real_async_trait]
+//! pub trait Bytes {
+//!     fn iter<'a>(&'a self) -> impl Iterator<Item = &'a u8> + 'a;
+//! }
+//! ```
+//!
+//! Such methods cannot have a default body.
+//!
+//! Async methods may also return `impl Trait` within their output, as in
+//! `async fn list<'a>(&'a self) -> Result<impl Iterator<Item = u32>, Errno>`. Each such `impl Trait`
+//! gets an associated type of its own, which the `Output` of the future then refers to.
+//!
+//! ## `Send` variants
+//!
+//! Generic code that spawns futures usually needs to know that they are `Send`, without every
+//! implementor being forced to return `Send` futures. Passing `variant(Name: Bounds)` to the
+//! attribute on a trait generates a subtrait whose generated associated types all have these
+//! bounds, which is implemented for every implementor of the trait whose futures satisfy them:
+//!
+//! ```ignore
+//! #[real_async_trait(variant(SendRedoxScheme: Send))]
+//! pub trait RedoxScheme {
+//!     // ...
+//! }
+//!
+//! fn spawn_scheme<S: SendRedoxScheme + Send + Sync + 'static>(scheme: S) {
+//!     // ...
+//! }
+//! ```
+//!
+//! The futures of boxed methods, including provided ones, have no associated type to put these
+//! bounds on. A trait with boxed methods can therefore only have a variant bounded by `Send` alone,
+//! and only if all of its boxed methods are marked `#[real_async_trait(Send)]`.
+//!
+//! The bounds have to hold for the futures of all lifetimes of the methods, and the generated
+//! associated types require `Self` to outlive these lifetimes. Due to a limitation of the compiler,
+//! this requires `Self: 'static`, so only `'static` implementors implement the variant.
+//!
+//! ## Future sizes
+//!
+//! Zero-cost futures are only worth it as long as they stay small. For every impl, a hidden
+//! function named after the trait, e.g. `__real_async_trait_impl_FutureSizesFor_RedoxScheme()`,
+//! lists the name, size and alignment of the future returned by each async method. It is placed in
+//! the hidden `__real_async_trait_impl` module next to the impl, so that impls for types from other
+//! crates work as well. A limit can also be enforced at compile time, reporting methods with larger
+//! futures:
+//!
+//! ```ignore
+//! #[real_async_trait(max_future_size = 2048)]
+//! impl RedoxScheme for MyNothingScheme {
+//!     // ...
+//! }
+//! ```
+//!
+//! Given `max_future_size`, the sizes are also listed by an associated function of the same name,
+//! e.g. `MyNothingScheme::__real_async_trait_impl_FutureSizesFor_RedoxScheme()`. That function is
+//! left out for types that are evidently defined elsewhere, such as primitives, references and
+//! types from the standard library, whose futures are checked all the same. For other types from
+//! other crates, the compiler rejects it, so their impls cannot be given `max_future_size`. Boxed
+//! futures are not included, since they are only the size of a pointer.
+//!
+//! ## Documentation
+//!
+//! Since rustdoc only ever sees the expanded trait, each async method gets a paragraph appended to
+//! its documentation that shows the signature it was originally declared with, along with the
+//! type its future resolves to. The generated associated types are hidden from the docs.
+//!
+//! ## Generic methods
+//!
+//! Async methods may have type parameters, including anonymous ones given as `impl Trait`
+//! arguments, which the macro turns into named type parameters of the method. As the future holds
+//! on to the arguments, their types have to outlive the lifetimes of the method:
+//!
+//! ```ignore
+//! async fn send<'a>(&'a self, body: impl AsRef<[u8]> + 'a) -> usize;
+//! ```
+//!
+//! The where clauses of a method, and of the impl it is in, apply to its future as well, so impls
+//! may be generic too.
+//!
+//! The sizes of futures of generic methods and impls depend on the type parameters, so they are
+//! not listed, and not checked against `max_future_size`. Traits with generic async methods cannot have
+//! variants either.
+//!
+//! ## Arguments
+//!
+//! Like a native async fn, the generated future takes ownership of all arguments, including those
+//! that are never used or bound to `_`, so that guards such as `_permit: SemaphorePermit` are only
+//! dropped at the end of the future. Arguments bound by other patterns than plain identifiers are
+//! renamed in the signature, and destructured within the future.
+//!
+//! ## Qualifiers
+//!
+//! `async unsafe fn` stays `unsafe`, and since the future is created within the unsafe fn, lints
+//! such as `unsafe_op_in_unsafe_fn` apply to its body exactly like they would to a native async fn.
+//! Whether a method is `unsafe` has to match its declaration in the trait. Like for native async
+//! fns, `const async fn` is rejected, and so is an explicit ABI such as `async extern "C" fn`.
+//!
+//! ## Free and inherent async fns
+//!
+//! Applied to a free `async fn` or to an inherent impl, the attribute gives the future of each
+//! async fn a name, so that it can be stored in a struct field:
+//!
+//! ```ignore
+//! #[real_async_trait]
+//! pub async fn fetch<'a>(url: &'a str) -> Vec<u8> { /* ... */ }
+//!
+//! // expands to
+//! pub type FetchFuture<'a> = impl Future<Output = Vec<u8>>;
+//! pub fn fetch<'a>(url: &'a str) -> FetchFuture<'a> { /* ... */ }
+//! ```
+//!
+//! The alias is named after the fn, has the same visibility, and takes the lifetimes of the fn,
+//! preceded by those of the impl. Since an alias for every method is placed next to the impl,
+//! inherent impls within the same module should not have async methods of the same name.
+//!
+//! ## Modules
+//!
+//! The attribute can also be applied to an inline module, to expand a trait together with its
+//! impls:
+//!
+//! ```ignore
+//! #[real_async_trait(max_future_size = 256)]
+//! mod scheme {
+//!     pub trait RedoxScheme { /* ... */ }
+//!
+//!     impl RedoxScheme for MyNvmeDriver { /* ... */ }
+//!
+//!     #[real_async_trait(max_future_size = 64)]
+//!     impl RedoxScheme for MyRamDisk { /* ... */ }
+//! }
+//! ```
+//!
+//! Every trait in the module is expanded, and so is every impl of one of these traits, without
+//! requiring attributes of their own. Impls are checked against the declaration in the module, and
+//! several impls may share a module. Arguments given to the module apply to all impls in it,
+//! unless an item has a `#[real_async_trait(...)]` attribute with its own arguments. Other items,
+//! including impls of traits from elsewhere, are left as they are.
+//!
+//! ## Method attribute macros
+//!
+//! Attribute macros on the async methods of an impl, such as `#[tracing::instrument]`, would
+//! otherwise see a method that is no longer an `async fn`. Instead, such a method is split: the
+//! attribute macros are moved onto a private inherent helper with the original signature and body,
+//! which the method awaits.
+//!
+//! ```ignore
+//! #[real_async_trait(method_attributes(timed))]
+//! impl RedoxScheme for MyNvmeDriver {
+//!     #[tracing::instrument(skip(self))]
+//!     async fn open<'a>(&'a self, path: &'a [u8], flags: usize) -> Result<usize> { /* ... */ }
+//!
+//!     #[cfg_attr(feature = "metrics", timed)]
+//!     async fn read<'a>(&'a self, fd: usize, buf: &'a mut [u8]) -> Result<usize> { /* ... */ }
+//! }
+//! ```
+//!
+//! `instrument` is recognized by default, and other macros can be listed with
+//! `method_attributes(...)`, also when used within `cfg_attr`. Macros are matched by the last
+//! segment of their path. Unless it is given a `name`, `instrument` is given the name of the
+//! method, so that its span is not named after the helper. The helper of a trait method lives in
+//! an inherent impl, so its body cannot refer to the associated types of the trait as
+//! `Self::Type`, the impl cannot have type parameters that the implementing type does not use, and
+//! the implementing type has to be defined in the current crate.
+//!
+//! ## Instrumentation
+//!
+//! `instrument(Hooks)` wraps the future of every async method of an impl, so that the hooks are
+//! told whenever a poll of it is entered and left, and when it completes, without boxing it. The
+//! hooks are associated functions of the given type, or of a trait that it implements, which is in
+//! scope:
+//!
+//! ```ignore
+//! pub struct SchemeHooks;
+//!
+//! impl SchemeHooks {
+//!     pub fn enter(method: &'static str, trait_name: Option<&'static str>, self_ty: &'static str) { /* ... */ }
+//!     pub fn exit(method: &'static str, trait_name: Option<&'static str>, self_ty: &'static str) { /* ... */ }
+//!     pub fn complete(method: &'static str, trait_name: Option<&'static str>, self_ty: &'static str) { /* ... */ }
+//! }
+//!
+//! #[real_async_trait(instrument(crate::hooks::SchemeHooks))]
+//! pub trait RedoxScheme { /* ... */ }
+//! ```
+//!
+//! `self_ty` is the name of the implementing type, as returned by `core::any::type_name`. Hooks
+//! given to a trait apply to its provided methods and to all of its impls, unless an impl is given
+//! hooks of its own. Since these impls may be in other crates, a path to the hooks starting with
+//! `crate` refers to the crate of the trait.
+//!
+//! ## Metrics
+//!
+//! Given `metrics`, an impl records how the futures of its async methods are polled. Like the
+//! future sizes, they are returned by a hidden associated function named after the trait:
+//!
+//! ```ignore
+//! #[real_async_trait(metrics)]
+//! impl RedoxScheme for MyNvmeDriver { /* ... */ }
+//!
+//! for (method, polls, completed, cancelled, nanos) in MyNvmeDriver::__real_async_trait_impl_MetricsFor_RedoxScheme() {
+//!     // ...
+//! }
+//! ```
+//!
+//! For each method, the atomic counters hold how often its futures were polled, how many of them
+//! completed, how many were dropped before completing, and the total number of nanoseconds from
+//! the first poll of each future until its completion. The counters are shared by all
+//! instantiations of a generic impl, and nothing is generated without `metrics`. Since the function
+//! lives in an inherent impl, `metrics` is not supported for types from other crates.
+//!
+//! ## Interceptors
+//!
+//! Given `interceptor`, a trait also gets a wrapper that implements it by delegating to another
+//! implementation, calling hooks before and after every method with a receiver, e.g. for logging,
+//! auditing or access checks:
+//!
+//! ```ignore
+//! #[real_async_trait(interceptor)]
+//! pub trait RedoxScheme { /* ... */ }
+//!
+//! struct AuditLog;
+//!
+//! impl InterceptedHooks for AuditLog {
+//!     fn before(&self, method: &'static str, args: &[&dyn core::fmt::Debug]) { /* ... */ }
+//!     fn after(&self, method: &'static str, output: &dyn core::fmt::Debug) { /* ... */ }
+//! }
+//!
+//! let scheme = Intercepted::new(MyNvmeDriver::new(), AuditLog);
+//! ```
+//!
+//! The impl of `Intercepted<T, H>` is expanded like any other, so its associated future types are
+//! the unboxed futures of its methods, which await those of `T`. If some of these have to be `Send`,
+//! the impl requires `T` and `H` to be `Sync`, or `Send` for methods taking `&mut self` or `self`,
+//! since the futures hold on to the wrapper. Arguments and outputs that do not implement `Debug`,
+//! including those whose types are generic, are shown as `_`. Methods without a receiver are
+//! delegated without calling the hooks, and methods that take or return `Self` itself cannot be
+//! delegated. `interceptor(LoggedScheme)` names the wrapper `LoggedScheme`, and its hooks trait
+//! `LoggedSchemeHooks`, so that several traits in one module can have one. Within a module given
+//! the attribute, traits take `interceptor` in an attribute of their own.
+//!
+//! Like the impls of a trait, the wrapper has to be declared in a publicly reachable module:
+//! current nightlies crash when a trait with an interceptor lives in a private module and is
+//! re-exported from there.
+//!
 //! ## How it works
 //!
+//! Impls have to agree with the trait on which futures are `Send` or boxed, which the impl macro
+//! cannot see by itself. The trait expansion therefore also generates a hidden macro carrying the
+//! declaration of the trait, which is available under the same path as the trait, even from other
+//! crates. The expansion of an impl goes through that macro, and thus requires the trait to be in
+//! scope under the path that the impl names. Knowing the declaration also lets the impl macro
+//! report async methods that are missing, not async, or not part of the trait, before generating
+//! anything.
+//!
 //! Under the hood, this proc macro will insert generic associated types (GATs) for the the futures
 //! that are the return types of the async fns in the trait definition. The macro will generate the
 //! following for the `RedoxScheme` trait (simplified generated names):
@@ -97,11 +395,20 @@
 //!     fn write<'a>(&'a self, fd: usize, buf: &'a [u8]) -> Self::WriteFuture<'a>;
 //!     fn close<'a>(&'a self, fd: usize) -> Self::CloseFuture<'a>;
 //!
-//!     // Generic associated types, the return values are moved to here.
-//!     type OpenFuture<'a>: ::core::future::Future<Output = Result<FileDescriptor, Errno>> + 'a;
-//!     type ReadFuture<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a;
-//!     type WriteFuture<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a;
-//!     type CloseFuture<'a>: ::core::future::Future<Output = Result<(), Errno>> + 'a;
+//!     // Generic associated types, the return values are moved to here. The where clauses are
+//!     // inferred from the references in the method signatures.
+//!     type OpenFuture<'a>: ::core::future::Future<Output = Result<FileDescriptor, Errno>> + 'a
+//!     where
+//!         Self: 'a;
+//!     type ReadFuture<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a
+//!     where
+//!         Self: 'a;
+//!     type WriteFuture<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a
+//!     where
+//!         Self: 'a;
+//!     type CloseFuture<'a>: ::core::future::Future<Output = Result<(), Errno>> + 'a
+//!     where
+//!         Self: 'a;
 //! }
 //! ```
 //!
@@ -109,11 +416,13 @@
 //!
 //! ```ignore
 //!
-//! // Wrap everything in a private module to prevent the existential types from leaking.
-//! mod __private {
+//! // Wrap everything in a hidden module to prevent the existential types from leaking.
+//! #[doc(hidden)]
+//! pub mod __private {
 //!     impl RedoxScheme for MyNothingScheme {
 //!         // Async fns are downgraded here as well, and the same thing goes with the return
-//!         // values.
+//!         // values. The function bodies define the hidden types of the existential types.
+//!         #[define_opaque(OpenFutureExistentialType)]
 //!         fn open<'a>(&'a self, path: &'a [u8], flags: usize) -> Self::OpenFuture<'a> {
 //!             // All expressions in async fns are wrapped in async closures. The compiler will
 //!             // automagically figure out the actual types of the existential type aliases, even
@@ -134,19 +443,19 @@
 //!         // possible way to use types within type aliases within traits, that aren't publicly
 //!         // accessible. This we need async closures to avoid having to redefine our futures with
 //!         // custom state machines, or use type erased pointers, we'll use existential types.
-//!         type OpenFuture<'a> = OpenFutureExistentialType<'a>;
-//!         type ReadFuture<'a> = ReadFutureExistentialType<'a>;
-//!         type WriteFuture<'a> = WriteFutureExistentialType<'a>;
-//!         type CloseFuture<'a> = CloseFutureExistentialType<'a>;
+//!         type OpenFuture<'a> = OpenFutureExistentialType<'a> where Self: 'a;
+//!         type ReadFuture<'a> = ReadFutureExistentialType<'a> where Self: 'a;
+//!         type WriteFuture<'a> = WriteFutureExistentialType<'a> where Self: 'a;
+//!         type CloseFuture<'a> = CloseFutureExistentialType<'a> where Self: 'a;
 //!     }
 //!     // This is where the return values actually are defined. At the moment these type alises
 //!     // with impl trait can only occur outside of the trait itself, unfortunately. There can
 //!     // only be one type that this type alias refers to, which the compiler will keep track of.
-//!     type OpenFutureExistentialType<'a> = impl Future<Output = Result<FileDescriptor, Errno>> +
+//!     pub type OpenFutureExistentialType<'a> = impl Future<Output = Result<FileDescriptor, Errno>> +
 //!     'a;
-//!     type ReadFutureExistentialType<'a> = impl Future<Output = Result<usize, Errno>> + 'a;
-//!     type WriteFutureExistentialType<'a> = impl Future<Output = Result<usize, Errno>> + 'a;
-//!     type CloseFutureExistentialType<'a> = impl Future<Output = Result<(), Errno>> + 'a;
+//!     pub type ReadFutureExistentialType<'a> = impl Future<Output = Result<usize, Errno>> + 'a;
+//!     pub type WriteFutureExistentialType<'a> = impl Future<Output = Result<usize, Errno>> + 'a;
+//!     pub type CloseFutureExistentialType<'a> = impl Future<Output = Result<(), Errno>> + 'a;
 //! }
 //! ```
 //!
@@ -155,35 +464,131 @@ extern crate proc_macro;
 
 use std::{collections::HashSet, str::FromStr};
 use std::{iter, mem};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use proc_macro2::{Span, TokenStream};
-use quote::quote;
+use proc_macro2::{Group, Span, TokenStream, TokenTree};
+use quote::{quote, quote_spanned, ToTokens};
 use syn::{Attribute, punctuated::Punctuated};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
 use syn::token;
 use syn::{
     AngleBracketedGenericArguments, Binding, Block, Expr, ExprAsync, FnArg, GenericArgument,
-    GenericParam, Generics, Ident, ImplItem, ImplItemType, ItemImpl, ItemTrait, ItemType, Lifetime,
-    LifetimeDef, PatType, Path, PathArguments, PathSegment, ReturnType, Signature, Stmt, Token,
-    TraitBound, TraitBoundModifier, TraitItem, TraitItemType, Type, TypeImplTrait, TypeParamBound,
-    TypePath, TypeReference, TypeTuple, Visibility,
+    GenericParam, Generics, Ident, ImplItem, ImplItemMethod, ImplItemType, Item, ItemFn, ItemImpl, ItemMod, ItemTrait, ItemType, Lifetime,
+    LitInt, Pat, PatIdent, PatType, Path, PathArguments, PathSegment, QSelf, Receiver,
+    ReturnType,
+    Signature, Stmt, Token,
+    TraitBound, TraitBoundModifier, TraitItem, TraitItemMethod, TraitItemType, Type, TypeImplTrait, TypeParamBound,
+    TypePath, TypeReference, TypeTuple, VisPublic, Visibility, WherePredicate,
 };
 
 mod tests;
 
-struct LifetimeVisitor;
+/// Finds lifetimes in a signature that are hidden, and thus cannot be named by the generated
+/// associated types and existential types.
+struct LifetimeVisitor {
+    errors: Vec<syn::Error>,
+}
 
 impl<'ast> syn::visit::Visit<'ast> for LifetimeVisitor {
     fn visit_type_reference(&mut self, i: &'ast TypeReference) {
         if i.lifetime.is_none() {
-            panic!("Reference at {:?} lacked an explicit lifetime, which is required by this proc macro", i.and_token.span);
+            self.errors.push(syn::Error::new(
+                i.and_token.span,
+                "this reference lacks an explicit lifetime, which is required by this proc macro",
+            ));
+        }
+        syn::visit::visit_type_reference(self, i);
+    }
+    fn visit_lifetime(&mut self, i: &'ast Lifetime) {
+        if i.ident == "_" {
+            self.errors.push(syn::Error::new(
+                i.span(),
+                "`'_` cannot be named by the generated types; use a lifetime parameter of the fn instead",
+            ));
         }
     }
+    // Lifetimes elided within `fn(&T)` and `Fn(&T)` are higher-ranked, rather than hidden.
+    fn visit_type_bare_fn(&mut self, _: &'ast syn::TypeBareFn) {}
+    fn visit_parenthesized_generic_arguments(&mut self, _: &'ast syn::ParenthesizedGenericArguments) {}
 }
 
-fn handle_item_impl(mut item: ItemImpl) -> TokenStream {
+fn handle_item_impl(
+    mut item: ItemImpl,
+    args: &RealAsyncTraitArgs,
+    trait_declaration: Option<&ItemTrait>,
+    module_ident: &Ident,
+) -> TokenStream {
+    if let Some(trait_declaration) = trait_declaration {
+        if let Err(error) = check_against_declaration(&item, trait_declaration) {
+            return error.to_compile_error();
+        }
+    }
+
+    // Inherent methods returning `impl Trait` are left as they are, and thus not validated.
+    let validation_errors = item
+        .items
+        .iter()
+        .filter_map(|impl_item| match impl_item {
+            ImplItem::Method(method) => Some(method),
+            _ => None,
+        })
+        .filter(|method| {
+            method.sig.asyncness.is_some() || (item.trait_.is_some() && returns_impl_trait(&method.sig))
+        })
+        .filter_map(|method| validate_signature(&method.sig).err())
+        .collect();
+    if let Err(error) = combine_errors(validation_errors) {
+        return error.to_compile_error();
+    }
+
     let mut existential_type_defs = Vec::new();
-    let mut gat_defs = Vec::new();
+    let mut gat_defs: Vec<ImplItemType> = Vec::new();
+    let mut future_size_checks = Vec::new();
+    let mut helpers = Vec::new();
+
+    // Hooks given to the impl take precedence over those given to the trait.
+    let hooks = args
+        .instrument
+        .clone()
+        .or_else(|| trait_declaration.and_then(declared_hooks))
+        .map(|path| InstrumentHooks {
+            path,
+            trait_name: item
+                .trait_
+                .as_ref()
+                .and_then(|(_, path, _)| path.segments.last())
+                .map(|segment| segment.ident.to_string()),
+        });
+
+    // With `metrics`, a hidden associated function returns where the futures of each async method
+    // record how they are polled. Like the future sizes, it is named after the trait.
+    let metrics_ident = args.metrics.then(|| {
+        Ident::new(
+            &match item.trait_ {
+                Some((_, ref path, _)) => format!(
+                    "__real_async_trait_impl_MetricsFor_{}",
+                    path.segments.last().expect("expected a trait path").ident
+                ),
+                None => "__real_async_trait_impl_Metrics".to_owned(),
+            },
+            Span::call_site(),
+        )
+    });
+    let mut metered_methods = Vec::new();
 
+    // The metrics wrap the hooks rather than the other way around, since they start as soon as the
+    // future is created.
+    let mut wrap_future = |mut future: Expr, method: &Ident| {
+        if let Some(ref hooks) = hooks {
+            future = instrument_future(future, hooks, method);
+        }
+        if let Some(ref metrics_ident) = metrics_ident {
+            future = metered_future(future, metrics_ident, metered_methods.len());
+            metered_methods.push(method.to_string());
+        }
+        future
+    };
 
     for method in item
         .items
@@ -195,173 +600,1405 @@ fn handle_item_impl(mut item: ItemImpl) -> TokenStream {
                 None
             }
         })
-        .filter(|method| method.sig.asyncness.is_some())
+        .filter(|method| method.sig.asyncness.is_some() || returns_impl_trait(&method.sig))
     {
+        // Inherent methods returning `impl Trait` are fine as they are.
+        if item.trait_.is_none() && method.sig.asyncness.is_none() {
+            continue;
+        }
+        let is_async = method.sig.asyncness.is_some();
+
+        let output_span = output_span(&method.sig);
+
+        let mut real_async_traits_attributes = match parse_attributes(&mut method.attrs) {
+            Ok(attributes) => attributes,
+            Err(error) => return error.to_compile_error(),
+        };
+        // Whether the future is `Send` or boxed is decided by the trait, so the method does not
+        // have to repeat it.
+        if let Some(trait_declaration) = trait_declaration {
+            real_async_traits_attributes
+                .extend(declared_attributes(trait_declaration, &method.sig.ident));
+        }
+
+        if is_async {
+            let trait_path = item.trait_.as_ref().map(|(_, path, _)| path);
+            helpers.extend(split_off_async_helper(method, helper_ident(trait_path, &method.sig), args));
+        }
         method.sig.asyncness = None;
 
-        validate_that_function_always_has_lifetimes(&method.sig);
+        desugar_impl_trait_arguments(&mut method.sig);
+        let (toplevel_params, function_params) =
+            already_defined_params(&item.generics, &method.sig.generics);
 
-        let (toplevel_lifetimes, function_lifetimes) =
-            already_defined_lifetimes(&item.generics, &method.sig.generics);
+        if is_async && real_async_traits_attributes.contains(&RealAsyncTraitAttributes::Boxed) {
+            // Overrides of provided methods have to return the same boxed future as the trait.
+            let bounds = future_lifetime_bounds(&method.sig, &toplevel_params)
+                .into_iter()
+                .chain(real_async_traits_attributes.into_iter().filter_map(|attr| send_bound(attr, output_span)))
+                .collect();
+            let method_return_ty = return_type(method.sig.output.clone());
 
-        let existential_type_name = format!(
-            "__real_async_trait_impl_ExistentialTypeFor_{}",
-            method.sig.ident
-        );
-        let existential_type_ident = Ident::new(&existential_type_name, Span::call_site());
+            method.sig.output = ReturnType::Type(
+                output_arrow(&method.sig),
+                Box::new(boxed_future_type(method_return_ty, bounds)),
+            );
+            let future = wrap_future(async_move_block(&mut method.sig, &mut method.block), &method.sig.ident);
+            method.block.stmts = vec![Stmt::Expr(boxed_future_expr(future, output_span))];
+            continue;
+        }
 
+        if item.trait_.is_none() {
+            // Without a trait to declare associated types, the future of an inherent method is
+            // named by a type alias next to the impl.
+            let (alias, future_size_check) = lower_to_named_future(
+                &mut method.sig,
+                &mut method.attrs,
+                &mut method.block,
+                &method.vis,
+                &item.generics,
+                Some(&item.self_ty),
+                real_async_traits_attributes.into_iter().filter_map(|attr| send_bound(attr, output_span)).collect(),
+            );
+            if let Some(Stmt::Expr(future)) = method.block.stmts.pop() {
+                method.block.stmts.push(Stmt::Expr(wrap_future(future, &method.sig.ident)));
+            }
+            existential_type_defs.push(alias);
+            future_size_checks.extend(future_size_check);
+            continue;
+        }
 
-        existential_type_defs.push(ItemType {
-            attrs: Vec::new(),
-            eq_token: Token!(=)(Span::call_site()),
-            generics: Generics {
-                gt_token: Some(Token!(>)(Span::call_site())),
-                lt_token: Some(Token!(<)(Span::call_site())),
-                params: toplevel_lifetimes
-                    .iter()
-                    .cloned()
-                    .map(GenericParam::Lifetime)
-                    .collect(),
-                where_clause: None,
-            },
-            ident: existential_type_ident,
-            semi_token: Token!(;)(Span::call_site()),
-            vis: Visibility::Inherited,
-            ty: Box::new(Type::ImplTrait(TypeImplTrait {
-                bounds: iter::once(TypeParamBound::Trait(future_trait_bound(return_type(
-                    method.sig.output.clone(),
-                ))))
-                .chain(
-                    toplevel_lifetimes
-                        .iter()
-                        .cloned()
-                        .map(|lifetime_def| TypeParamBound::Lifetime(lifetime_def.lifetime)),
+        let existential_type_ident = Ident::new(
+            &format!("__real_async_trait_impl_ExistentialTypeFor_{}", method.sig.ident),
+            located_at(method.sig.ident.span()),
+        );
+        let gat_ident = gat_ident_for_sig(&method.sig);
+
+        let mut method_return_ty = replace_self_type(
+            return_type(method.sig.output.clone()),
+            &item.self_ty,
+            item.trait_.as_ref().map(|(_, path, _)| path),
+        );
+        // Each `impl Trait` within the output of an async method gets an existential type of its
+        // own, matching the associated type that the trait generated for it.
+        let nested_bounds = if is_async {
+            replace_nested_impl_traits(&mut method_return_ty, |index| {
+                existential_path_type(
+                    nested_type_ident(&existential_type_ident, index),
+                    &toplevel_params,
                 )
+            })
+        } else {
+            Vec::new()
+        };
+
+        let opaque_types = iter::once((
+            gat_ident.clone(),
+            existential_type_ident.clone(),
+            returned_type_bounds(&method.sig, is_async, method_return_ty, &toplevel_params)
+                .into_iter()
+                .chain(real_async_traits_attributes.into_iter().filter_map(|attr| send_bound(attr, output_span)))
                 .collect(),
-                impl_token: Token!(impl)(Span::call_site()),
-            })),
-            type_token: Token!(type)(Span::call_site()),
-        });
+        ))
+        .chain(nested_bounds.into_iter().enumerate().map(|(index, bounds)| {
+            (
+                nested_type_ident(&gat_ident, index),
+                nested_type_ident(&existential_type_ident, index),
+                bounds,
+            )
+        }))
+        .collect::<Vec<(Ident, Ident, Punctuated<TypeParamBound, Token![+]>)>>();
 
-        let existential_type_path_for_impl = Path {
-            // self::__real_async_trait_impl_ExistentialTypeFor_FUNCTIONNAME
-            leading_colon: None,
-            segments: vec![
-                PathSegment {
-                    arguments: PathArguments::None,
-                    ident: Ident::new("self", Span::call_site()),
-                },
-                PathSegment {
-                    arguments: PathArguments::AngleBracketed(lifetime_angle_bracketed_bounds(
-                        toplevel_lifetimes
-                            .into_iter()
-                            .map(|lifetime_def| lifetime_def.lifetime),
-                    )),
-                    ident: Ident::new(&existential_type_name, Span::call_site()),
-                },
-            ]
-            .into_iter()
-            .collect(),
+        let gat_generics = {
+            let mut generics = param_generics(&function_params);
+            generics.make_where_clause().predicates.extend(gat_outlives_predicates(
+                &method.sig,
+                &item.generics,
+                &function_params,
+            ));
+            generics
+                .make_where_clause()
+                .predicates
+                .extend(where_predicates(&method.sig.generics));
+            remove_empty_where_clause(&mut generics);
+            generics
+        };
+        let existential_generics = {
+            let mut generics = param_generics(&toplevel_params);
+            generics.make_where_clause().predicates.extend(existential_where_predicates(
+                &item.generics,
+                &method.sig,
+                &function_params,
+                Some(&item.self_ty),
+                item.trait_.as_ref().map(|(_, path, _)| path),
+            ));
+            remove_empty_where_clause(&mut generics);
+            generics
         };
-        let existential_path_type = Type::Path(TypePath {
-            path: existential_type_path_for_impl,
-            qself: None,
-        });
 
-        let gat_ident = gat_ident_for_sig(&method.sig);
+        // A future resolving to another type than the method returns is reported on the
+        // existential type, which should then point at the output type of the method.
+        let existential_span = located_at(output_span);
+        for (gat_ident, existential_type_ident, bounds) in opaque_types.iter().cloned() {
+            existential_type_defs.push(ItemType {
+                attrs: forwarded_attributes(&method.attrs, true),
+                eq_token: Token!(=)(existential_span),
+                generics: existential_generics.clone(),
+                ident: existential_type_ident.clone(),
+                semi_token: Token!(;)(existential_span),
+                // The existential types have to be public, since they are reachable from the trait
+                // impl.
+                vis: Visibility::Public(VisPublic {
+                    pub_token: Token!(pub)(existential_span),
+                }),
+                ty: Box::new(Type::ImplTrait(TypeImplTrait {
+                    bounds,
+                    impl_token: Token!(impl)(existential_span),
+                })),
+                type_token: Token!(type)(existential_span),
+            });
 
-        gat_defs.push(ImplItemType {
-            attrs: Vec::new(),
-            defaultness: None,
-            eq_token: Token!(=)(Span::call_site()),
-            generics: Generics {
-                lt_token: Some(Token!(<)(Span::call_site())),
-                gt_token: Some(Token!(>)(Span::call_site())),
-                where_clause: None,
-                params: function_lifetimes
-                    .iter()
-                    .cloned()
-                    .map(GenericParam::Lifetime)
-                    .collect(),
-            },
-            ident: gat_ident.clone(),
-            semi_token: Token!(;)(Span::call_site()),
-            ty: existential_path_type.clone(),
-            type_token: Token!(type)(Span::call_site()),
-            vis: Visibility::Inherited,
+            let gat_def = ImplItemType {
+                attrs: forwarded_attributes(&method.attrs, true),
+                defaultness: None,
+                eq_token: Token!(=)(Span::call_site()),
+                generics: gat_generics.clone(),
+                ident: gat_ident,
+                semi_token: Token!(;)(Span::call_site()),
+                ty: existential_path_type(existential_type_ident, &toplevel_params),
+                type_token: Token!(type)(Span::call_site()),
+                vis: Visibility::Inherited,
+            };
+            // A future resolving to another type than the trait declares is reported on the
+            // associated type, which should then point at the output type of the method.
+            gat_defs.push(
+                syn::parse2(respan(gat_def.to_token_stream(), output_span))
+                    .expect("failed to parse respanned associated type"),
+            );
+        }
+
+        // The associated type of a method that is deprecated in the trait is deprecated as well, so
+        // the method returns the existential type instead, rather than allowing the lint for its
+        // body.
+        let is_declared_deprecated = trait_declaration.is_some_and(|declaration| {
+            declaration.items.iter().any(|item| {
+                matches!(item, TraitItem::Method(declared) if declared.sig.ident == method.sig.ident && is_deprecated(&declared.attrs))
+            })
         });
+        let output_ty: Type = if is_declared_deprecated {
+            let existential_path = existential_path_type(existential_type_ident.clone(), &toplevel_params);
+            syn::parse2(respan(existential_path.into_token_stream(), located_at(output_span)))
+                .expect("failed to parse respanned return type")
+        } else {
+            self_gat_type(gat_ident, &function_params, output_span).into()
+        };
 
-        let gat_self_type = self_gat_type(
-            gat_ident,
-            function_lifetimes
-                .into_iter()
-                .map(|lifetime_def| lifetime_def.lifetime),
-        );
+        method.sig.output = ReturnType::Type(output_arrow(&method.sig), Box::new(output_ty));
 
-        method.sig.output = ReturnType::Type(
-            Token!(->)(Span::call_site()),
-            Box::new(gat_self_type.into()),
-        );
+        if is_async && !has_type_params(&toplevel_params) {
+            // Lifetimes do not affect the layout, so any instantiation of the future type will do.
+            let static_lifetimes = lifetime_path_arguments(
+                iter::repeat_n(
+                    Lifetime::new("'static", Span::call_site()),
+                    toplevel_params.len(),
+                ),
+            );
+            future_size_checks.push((
+                forwarded_attributes(&method.attrs, false),
+                method.sig.ident.clone(),
+                quote! { #existential_type_ident #static_lifetimes },
+            ));
+        }
 
-        let method_stmts = mem::replace(&mut method.block.stmts, Vec::new());
+        // The method body is what defines the hidden type of the existential type alias, and
+        // through its `Output`, those of the nested ones as well.
+        method.attrs.push(syn::parse_quote! {
+            #[define_opaque(#existential_type_ident)]
+        });
 
-        method.block.stmts = vec![Stmt::Expr(Expr::Async(ExprAsync {
-            async_token: Token!(async)(Span::call_site()),
-            attrs: Vec::new(),
-            block: Block {
-                brace_token: token::Brace {
-                    span: Span::call_site(),
-                },
-                stmts: method_stmts,
-            },
-            capture: Some(Token!(move)(Span::call_site())),
-        }))];
+        if is_async {
+            let future = wrap_future(async_move_block(&mut method.sig, &mut method.block), &method.sig.ident);
+            method.block.stmts = vec![Stmt::Expr(future)];
+        }
     }
 
     item.items.extend(gat_defs.into_iter().map(Into::into));
 
-    quote! {
+    // The helpers of inherent methods can be placed next to them, whereas those of trait methods
+    // need an inherent impl of their own, which cannot have parameters that only the trait uses.
+    let helper_impl = if helpers.is_empty() {
+        None
+    } else if item.trait_.is_none() {
+        item.items.extend(helpers.into_iter().map(ImplItem::Method));
+        None
+    } else if is_foreign_type(&item.self_ty) {
+        return syn::Error::new(
+            item.self_ty.span(),
+            format!(
+                "attribute macros on async methods are not supported in impls for `{}`, since their helpers need an inherent impl, which types from other crates cannot have",
+                tokens_to_string(item.self_ty.to_token_stream())
+            ),
+        )
+        .to_compile_error();
+    } else if !self_ty_mentions_all_params(&item) {
+        return syn::Error::new(
+            item.self_ty.span(),
+            "attribute macros on async methods are only supported in impls whose parameters are all used by the implementing type",
+        )
+        .to_compile_error();
+    } else {
+        let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+        let self_ty = &item.self_ty;
+        Some(quote! {
+            impl #impl_generics #self_ty #where_clause {
+                #(#helpers)*
+            }
+        })
+    };
 
-        mod __real_async_trait_impl {
-            use super::*;
+    // A hidden associated function lists the name, size and alignment of the future returned by
+    // each async method. It is named after the trait, since a type may implement several traits.
+    let future_sizes_ident = Ident::new(
+        &match item.trait_ {
+            Some((_, ref path, _)) => format!(
+                "__real_async_trait_impl_FutureSizesFor_{}",
+                path.segments.last().expect("expected a trait path").ident
+            ),
+            None => "__real_async_trait_impl_FutureSizes".to_owned(),
+        },
+        Span::call_site(),
+    );
+    let future_size_entries = future_size_checks.iter().map(|(attrs, ident, ty)| {
+        let name = ident.to_string();
+        quote! {
+            #(#attrs)*
+            (#name, ::core::mem::size_of::<#ty>(), ::core::mem::align_of::<#ty>())
+        }
+    });
+    let future_sizes_fn = quote! {
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        pub fn #future_sizes_ident() -> &'static [(&'static str, usize, usize)] {
+            const FUTURE_SIZES: &[(&str, usize, usize)] = &[#(#future_size_entries),*];
+            FUTURE_SIZES
+        }
+    };
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let self_ty = &item.self_ty;
+
+    // The sizes of the futures of a trait impl are listed by a function within its module, since
+    // the implementing type may be defined in another crate. Only given `max_future_size` are they
+    // also listed by an associated function of the type, like those of an inherent impl, unless the
+    // type is evidently foreign. An inherent impl cannot have parameters that only the trait uses,
+    // but then, the futures of an impl that is generic over types have no known sizes anyway.
+    let future_sizes_fn = if item.trait_.is_none() {
+        self_ty_mentions_all_params(&item).then(|| {
+            quote! {
+                impl #impl_generics #self_ty #where_clause {
+                    #future_sizes_fn
+                }
+            }
+        })
+    } else if args.max_future_size.is_some() {
+        let inherent_impl = (!is_foreign_type(self_ty) && self_ty_mentions_all_params(&item)).then(|| {
+            quote_spanned! {located_at(self_ty.span())=>
+                impl #impl_generics #self_ty #where_clause {
+                    #[doc(hidden)]
+                    #[allow(non_snake_case)]
+                    pub fn #future_sizes_ident() -> &'static [(&'static str, usize, usize)] {
+                        #future_sizes_ident()
+                    }
+                }
+            }
+        });
+        Some(quote! {
+            #future_sizes_fn
+
+            #inherent_impl
+        })
+    } else {
+        Some(future_sizes_fn)
+    };
+
+    // Every metered method has an entry, even if it is configured out, so that the indices stay the
+    // same.
+    let metrics_fn = match metrics_ident {
+        Some(_) if is_foreign_type(&item.self_ty) => {
+            return syn::Error::new(
+                item.self_ty.span(),
+                format!(
+                    "`metrics` is not supported in impls for `{}`, since the metrics are returned by an inherent associated function, which types from other crates cannot have",
+                    tokens_to_string(item.self_ty.to_token_stream())
+                ),
+            )
+            .to_compile_error();
+        }
+        Some(_) if !self_ty_mentions_all_params(&item) => {
+            return syn::Error::new(
+                item.self_ty.span(),
+                "`metrics` is only supported in impls whose parameters are all used by the implementing type",
+            )
+            .to_compile_error();
+        }
+        Some(ref metrics_ident) => {
+            let count = metered_methods.len();
+            // The entries are tuples, since a proc-macro crate cannot export a type for them, and
+            // a generated one would clash between the inherent impls of a module.
+            Some(quote! {
+                impl #impl_generics #self_ty #where_clause {
+                    #[doc = "The metrics of the async methods of this impl, as one entry per method."]
+                    #[doc = ""]
+                    #[doc = "Each entry holds the name of the method, how often its futures were polled, how many of them completed, how many were dropped before completing, and the total number of nanoseconds from their first poll until completion."]
+                    #[doc(hidden)]
+                    #[allow(non_snake_case)]
+                    pub fn #metrics_ident() -> &'static [(
+                        &'static str,
+                        ::core::sync::atomic::AtomicU64,
+                        ::core::sync::atomic::AtomicU64,
+                        ::core::sync::atomic::AtomicU64,
+                        ::core::sync::atomic::AtomicU64,
+                    )] {
+                        static METRICS: [(
+                            &str,
+                            ::core::sync::atomic::AtomicU64,
+                            ::core::sync::atomic::AtomicU64,
+                            ::core::sync::atomic::AtomicU64,
+                            ::core::sync::atomic::AtomicU64,
+                        ); #count] = [#((
+                            #metered_methods,
+                            ::core::sync::atomic::AtomicU64::new(0),
+                            ::core::sync::atomic::AtomicU64::new(0),
+                            ::core::sync::atomic::AtomicU64::new(0),
+                            ::core::sync::atomic::AtomicU64::new(0),
+                        )),*];
+                        &METRICS
+                    }
+                }
+            })
+        }
+        None => None,
+    };
+
+    let max_future_size_checks = args.max_future_size.into_iter().flat_map(|max_future_size| {
+        future_size_checks
+            .iter()
+            .map(move |(attrs, ident, ty)| max_future_size_check(max_future_size, attrs, ident, ty))
+    });
+
+    let expanded = quote! {
+        #item
+
+        #helper_impl
+
+        #(#existential_type_defs)*
+
+        #future_sizes_fn
+
+        #metrics_fn
+
+        #(#max_future_size_checks)*
+    };
 
-            #item
+    // The names of the future types of inherent methods are part of the API, whereas those of trait
+    // impls are only reachable through the associated types.
+    if item.trait_.is_none() {
+        return expanded;
+    }
+    quote! {
+        #[doc(hidden)]
+        pub mod #module_ident {
+            use super::*;
 
-            #(#existential_type_defs)*
+            #expanded
         }
     }
 }
 
-fn return_type(retval: ReturnType) -> Type {
-    match retval {
-        ReturnType::Default => Type::Tuple(TypeTuple {
-            elems: Punctuated::new(),
-            paren_token: token::Paren {
-                span: Span::call_site(),
-            },
-        }),
-        ReturnType::Type(_, ty) => *ty,
+/// Asserts at compile time that the future of a method is no larger than `max_future_size`. A
+/// future that is too large is reported at the name of the method.
+fn max_future_size_check(max_future_size: usize, attrs: &[Attribute], ident: &Ident, ty: &TokenStream) -> TokenStream {
+    let max_future_size_lit = proc_macro2::Literal::usize_unsuffixed(max_future_size);
+    let message = format!(
+        "the future returned by `{}` is larger than the `max_future_size` of {} bytes",
+        ident, max_future_size
+    );
+    quote_spanned! {located_at(ident.span())=>
+        #(#attrs)*
+        const _: () = ::core::assert!(::core::mem::size_of::<#ty>() <= #max_future_size_lit, #message);
     }
 }
 
-fn future_trait_bound(fn_output_ty: Type) ->TraitBound {
-    const FUTURE_TRAIT_PATH_STR: &str = "::core::future::Future";
-    const FUTURE_TRAIT_OUTPUT_IDENT_STR: &str = "Output";
+/// Whether a type is evidently defined outside of the current crate, such that it cannot have
+/// inherent impls: primitives, types from the standard library, including those of its prelude,
+/// and types that are not paths. Other foreign types are only caught by the compiler.
+fn is_foreign_type(ty: &Type) -> bool {
+    match ty {
+        Type::Group(group) => is_foreign_type(&group.elem),
+        Type::Paren(paren) => is_foreign_type(&paren.elem),
+        Type::Path(TypePath { qself: None, path }) => {
+            let first = &path.segments[0].ident;
+            ["std", "core", "alloc"].iter().any(|krate| first == krate)
+                || (path.leading_colon.is_none()
+                    && path.segments.len() == 1
+                    && [
+                        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8",
+                        "i16", "i32", "i64", "i128", "isize", "f32", "f64", "Box", "Option",
+                        "Result", "String", "Vec",
+                    ]
+                    .iter()
+                    .any(|name| first == name))
+        }
+        Type::Path(_) => false,
+        _ => true,
+    }
+}
 
-    let mut future_trait_path =
-        syn::parse2::<Path>(TokenStream::from_str(FUTURE_TRAIT_PATH_STR).unwrap())
-            .expect("failed to parse `::core::future::Future` as a syn `Path`");
+/// The hooks given as `instrument(..)`, along with the trait whose methods they are told about.
+struct InstrumentHooks {
+    path: Path,
+    trait_name: Option<String>,
+}
 
-    let future_angle_bracketed_args = AngleBracketedGenericArguments {
-        colon2_token: None, // FIXME
-        lt_token: Token!(<)(Span::call_site()),
-        gt_token: Token!(>)(Span::call_site()),
-        args: iter::once(GenericArgument::Binding(Binding {
-            ident: Ident::new(FUTURE_TRAIT_OUTPUT_IDENT_STR, Span::call_site()),
-            eq_token: Token!(=)(Span::call_site()),
-            ty: fn_output_ty,
-        }))
-        .collect(),
+/// The hooks that a trait declaration was given as `instrument(..)`, if any.
+fn declared_hooks(trait_declaration: &ItemTrait) -> Option<Path> {
+    take_item_args(&mut trait_declaration.attrs.clone())
+        .ok()
+        .flatten()
+        .and_then(|args| args.instrument)
+}
+
+/// Wraps the future of an async method, so that the hooks are told about entering and leaving each
+/// poll, and about its completion, along with the name of the type that implements the method. The
+/// future is pinned within the wrapping future, rather than boxed.
+fn instrument_future(future: Expr, hooks: &InstrumentHooks, method: &Ident) -> Expr {
+    let path = &hooks.path;
+    let method_name = method.to_string();
+    let trait_name = match hooks.trait_name {
+        Some(ref trait_name) => quote! { ::core::option::Option::Some(#trait_name) },
+        None => quote! { ::core::option::Option::None },
+    };
+    let self_ty = quote! { ::core::any::type_name::<Self>() };
+
+    syn::parse_quote! {
+        async move {
+            let mut future = ::core::pin::pin!(#future);
+            ::core::future::poll_fn(move |cx| {
+                <#path>::enter(#method_name, #trait_name, #self_ty);
+                let poll = ::core::future::Future::poll(future.as_mut(), cx);
+                <#path>::exit(#method_name, #trait_name, #self_ty);
+                if poll.is_ready() {
+                    <#path>::complete(#method_name, #trait_name, #self_ty);
+                }
+                poll
+            })
+            .await
+        }
+    }
+}
+
+/// Wraps the future of an async method, so that it records how often it was polled, whether it
+/// completed or was dropped before that, and how long it took from the first poll until completion,
+/// in the `index`th entry of the metrics of the impl. The entry holds the name, and the polls,
+/// completions, cancellations and nanoseconds, as fields `0` to `4`.
+fn metered_future(future: Expr, metrics_ident: &Ident, index: usize) -> Expr {
+    // The guard is created along with the future, so that futures dropped before their first poll
+    // count as cancelled as well. Local names are prefixed, since the future captures arguments by
+    // name.
+    syn::parse_quote! {
+        {
+            struct __RealAsyncTraitImplMetrics {
+                entry: &'static (
+                    &'static str,
+                    ::core::sync::atomic::AtomicU64,
+                    ::core::sync::atomic::AtomicU64,
+                    ::core::sync::atomic::AtomicU64,
+                    ::core::sync::atomic::AtomicU64,
+                ),
+                started: ::core::option::Option<::std::time::Instant>,
+                completed: bool,
+            }
+            impl ::core::ops::Drop for __RealAsyncTraitImplMetrics {
+                fn drop(&mut self) {
+                    if !self.completed {
+                        self.entry.3.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+                    }
+                }
+            }
+            let mut __real_async_trait_impl_metrics = __RealAsyncTraitImplMetrics {
+                entry: &Self::#metrics_ident()[#index],
+                started: ::core::option::Option::None,
+                completed: false,
+            };
+            async move {
+                let mut future = ::core::pin::pin!(#future);
+                ::core::future::poll_fn(move |cx| {
+                    let metrics = &mut __real_async_trait_impl_metrics;
+                    metrics.entry.1.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+                    let started = *metrics.started.get_or_insert_with(::std::time::Instant::now);
+                    let poll = ::core::future::Future::poll(future.as_mut(), cx);
+                    if poll.is_ready() {
+                        metrics.completed = true;
+                        metrics.entry.2.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
+                        let nanos = <u64 as ::core::convert::TryFrom<u128>>::try_from(started.elapsed().as_nanos())
+                            .unwrap_or(u64::MAX);
+                        metrics.entry.4.fetch_add(nanos, ::core::sync::atomic::Ordering::Relaxed);
+                    }
+                    poll
+                })
+                .await
+            }
+        }
+    }
+}
+
+/// Whether every type and const parameter of an impl appears in its self type, as required for an
+/// inherent impl with the same parameters.
+fn self_ty_mentions_all_params(item: &ItemImpl) -> bool {
+    item.generics
+        .type_params()
+        .map(|param| &param.ident)
+        .chain(item.generics.const_params().map(|param| &param.ident))
+        .all(|ident| mentions_type_param(&item.self_ty, ident))
+}
+
+/// Attribute macros known to require an `async fn`, recognized by the last segment of their path.
+const METHOD_ATTRIBUTE_MACROS: &[&str] = &["instrument"];
+
+/// Whether an attribute on an async method is a macro that has to see an `async fn`, either one of
+/// [`METHOD_ATTRIBUTE_MACROS`] or one given as `method_attributes(..)`, or `cfg_attr` containing one.
+fn is_method_attribute_macro(attr: &Attribute, args: &RealAsyncTraitArgs) -> bool {
+    let is_macro = |path: &Path| match path.segments.last() {
+        Some(segment) => {
+            METHOD_ATTRIBUTE_MACROS.iter().any(|name| segment.ident == name)
+                || args.method_attributes.iter().any(|macro_path| {
+                    macro_path.segments.last().is_some_and(|last| last.ident == segment.ident)
+                })
+        }
+        None => false,
+    };
+    if attr.path.is_ident("cfg_attr") {
+        attr.parse_args_with(cfg_attr_contents)
+            .is_ok_and(|(_, attributes)| attributes.iter().any(|(path, _)| is_macro(path)))
+    } else {
+        is_macro(&attr.path)
+    }
+}
+
+/// The predicate and the attributes, as their path and remaining tokens, of
+/// `cfg_attr(predicate, attributes..)`.
+fn cfg_attr_contents(input: ParseStream) -> syn::Result<(syn::Meta, Vec<(Path, TokenStream)>)> {
+    let predicate = input.parse()?;
+    let mut attributes = Vec::new();
+    while !input.is_empty() {
+        input.parse::<Token![,]>()?;
+        if input.is_empty() {
+            break;
+        }
+        let path = input.call(Path::parse_mod_style)?;
+        let mut tokens = TokenStream::new();
+        while !input.is_empty() && !input.peek(Token![,]) {
+            tokens.extend(iter::once(input.parse::<TokenTree>()?));
+        }
+        attributes.push((path, tokens));
+    }
+    Ok((predicate, attributes))
+}
+
+/// Gives recognized `instrument` attributes, also within `cfg_attr`, the name of the method as the
+/// name of their span, unless they name it themselves, since it would otherwise be named after the
+/// helper.
+fn name_instrument_span(attr: &mut Attribute, method_ident: &Ident) {
+    let is_instrument = |path: &Path| path.segments.last().is_some_and(|segment| segment.ident == "instrument");
+    if is_instrument(&attr.path) {
+        attr.tokens = with_span_name(mem::take(&mut attr.tokens), method_ident);
+    } else if attr.path.is_ident("cfg_attr") {
+        let (predicate, attributes) = match attr.parse_args_with(cfg_attr_contents) {
+            Ok((predicate, attributes)) if attributes.iter().any(|(path, _)| is_instrument(path)) => {
+                (predicate, attributes)
+            }
+            _ => return,
+        };
+        let attributes = attributes.into_iter().map(|(path, tokens)| {
+            let tokens = if is_instrument(&path) {
+                with_span_name(tokens, method_ident)
+            } else {
+                tokens
+            };
+            quote! { #path #tokens }
+        });
+        attr.tokens = quote! { (#predicate, #(#attributes),*) };
+    }
+}
+
+/// The arguments of an `instrument` attribute, with `name = "method"` added if there is no `name`.
+fn with_span_name(tokens: TokenStream, method_ident: &Ident) -> TokenStream {
+    let name = method_ident.to_string();
+    let mut trees = tokens.clone().into_iter();
+    let group = match (trees.next(), trees.next()) {
+        (None, _) => return quote! { (name = #name) },
+        (Some(TokenTree::Group(group)), None) if group.delimiter() == proc_macro2::Delimiter::Parenthesis => group,
+        _ => return tokens,
+    };
+    let args = group.stream().into_iter().collect::<Vec<_>>();
+    let names_span = args.windows(2).any(|pair| {
+        matches!(&pair[0], TokenTree::Ident(ident) if ident == "name")
+            && matches!(&pair[1], TokenTree::Punct(punct) if punct.as_char() == '=')
+    });
+    if names_span {
+        return tokens;
+    }
+    let separator = match args.last() {
+        None => None,
+        Some(TokenTree::Punct(punct)) if punct.as_char() == ',' => None,
+        Some(_) => Some(quote! { , }),
+    };
+    let mut named = proc_macro2::Group::new(
+        proc_macro2::Delimiter::Parenthesis,
+        quote! { #(#args)* #separator name = #name },
+    );
+    named.set_span(group.span());
+    quote! { #named }
+}
+
+/// The name of the helper that an async method with attribute macros is split into, e.g.
+/// `__real_async_trait_impl_Greeter_greet`.
+fn helper_ident(trait_path: Option<&Path>, sig: &Signature) -> Ident {
+    let name = match trait_path.and_then(|path| path.segments.last()) {
+        Some(segment) => format!("__real_async_trait_impl_{}_{}", segment.ident, sig.ident),
+        None => format!("__real_async_trait_impl_{}", sig.ident),
+    };
+    Ident::new(&name, located_at(sig.ident.span()))
+}
+
+/// Moves the attribute macros of an async method that have to see an `async fn` onto a private
+/// inherent helper with the signature and body of the method, which the method then awaits.
+fn split_off_async_helper(
+    method: &mut ImplItemMethod,
+    helper_ident: Ident,
+    args: &RealAsyncTraitArgs,
+) -> Option<ImplItemMethod> {
+    let (mut moved_attrs, attrs): (Vec<_>, Vec<_>) = mem::take(&mut method.attrs)
+        .into_iter()
+        .partition(|attr| is_method_attribute_macro(attr, args));
+    method.attrs = attrs;
+    if moved_attrs.is_empty() {
+        return None;
+    }
+    for attr in moved_attrs.iter_mut() {
+        name_instrument_span(attr, &method.sig.ident);
+    }
+
+    let mut helper = method.clone();
+    helper.attrs = iter::once(syn::parse_quote! { #[allow(non_snake_case)] })
+        .chain(forwarded_attributes(&method.attrs, false))
+        .chain(moved_attrs)
+        .collect();
+    helper.vis = Visibility::Inherited;
+    helper.defaultness = None;
+    helper.sig.ident = helper_ident.clone();
+
+    // Arguments bound by other patterns than plain identifiers are destructured by the helper.
+    let mut call_args = Vec::new();
+    for (index, input) in method.sig.inputs.iter_mut().enumerate() {
+        match input {
+            FnArg::Receiver(Receiver { self_token, .. }) => call_args.push(quote! { #self_token }),
+            FnArg::Typed(PatType { ref mut pat, .. }) => match **pat {
+                Pat::Ident(PatIdent {
+                    by_ref: None,
+                    ref ident,
+                    subpat: None,
+                    ..
+                }) => call_args.push(quote! { #ident }),
+                _ => {
+                    let arg_ident = quote::format_ident!("__real_async_trait_impl_arg{}", index);
+                    **pat = syn::parse_quote! { #arg_ident };
+                    call_args.push(quote! { #arg_ident });
+                }
+            },
+        }
+    }
+    let explicit_params = method
+        .sig
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(&param.ident),
+            GenericParam::Const(param) => Some(&param.ident),
+            GenericParam::Lifetime(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let turbofish = if explicit_params.is_empty() {
+        None
+    } else {
+        Some(quote! { ::<#(#explicit_params),*> })
+    };
+    let call = quote! { Self::#helper_ident #turbofish(#(#call_args),*).await };
+    method.block.stmts = vec![Stmt::Expr(if method.sig.unsafety.is_some() {
+        syn::parse_quote! { unsafe { #call } }
+    } else {
+        syn::parse_quote! { #call }
+    })];
+
+    Some(helper)
+}
+
+fn handle_item_fn(mut item: ItemFn, args: &RealAsyncTraitArgs) -> TokenStream {
+    if item.sig.asyncness.is_none() {
+        return syn::Error::new(
+            item.sig.fn_token.span,
+            "#[real_async_trait] can only be applied to async fns",
+        )
+        .to_compile_error();
+    }
+    if let Err(error) = validate_signature(&item.sig) {
+        return error.to_compile_error();
+    }
+
+    desugar_impl_trait_arguments(&mut item.sig);
+    let (alias, future_size_check) = lower_to_named_future(
+        &mut item.sig,
+        &mut item.attrs,
+        &mut item.block,
+        &item.vis,
+        &Generics::default(),
+        None,
+        Vec::new(),
+    );
+    let max_future_size_check = args
+        .max_future_size
+        .zip(future_size_check)
+        .map(|(max_future_size, (attrs, ident, ty))| max_future_size_check(max_future_size, &attrs, &ident, &ty));
+
+    quote! {
+        #alias
+
+        #item
+
+        #max_future_size_check
+    }
+}
+
+/// The name of the type alias for the future of a free or inherent async fn, e.g. `FetchFuture`
+/// for `fetch`.
+fn named_future_ident(sig: &Signature) -> Ident {
+    let name = sig.ident.to_string();
+    let camel_case = name
+        .trim_start_matches("r#")
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    Ident::new(&format!("{}Future", camel_case), sig.ident.span())
+}
+
+/// Turns a free or inherent async fn into a plain fn returning a named existential type. Returns
+/// the type alias, and the attributes, name and type for checking the size of the future.
+fn lower_to_named_future(
+    sig: &mut Signature,
+    attrs: &mut Vec<Attribute>,
+    block: &mut Block,
+    vis: &Visibility,
+    toplevel_generics: &Generics,
+    self_ty: Option<&Type>,
+    extra_bounds: Vec<TypeParamBound>,
+) -> (ItemType, Option<(Vec<Attribute>, Ident, TokenStream)>) {
+    sig.asyncness = None;
+    let (params, function_params) = already_defined_params(toplevel_generics, &sig.generics);
+    let params = &params[..];
+    let alias_ident = named_future_ident(sig);
+    let alias_doc = format!("The future returned by `{}`.", sig.ident);
+
+    let mut fn_output_ty = return_type(sig.output.clone());
+    if let Some(self_ty) = self_ty {
+        fn_output_ty = replace_self_type(fn_output_ty, self_ty, None);
+    }
+    let alias_generics = {
+        let mut generics = param_generics(params);
+        generics
+            .make_where_clause()
+            .predicates
+            .extend(implied_outlives_predicates(sig));
+        generics.make_where_clause().predicates.extend(existential_where_predicates(
+            toplevel_generics,
+            sig,
+            &function_params,
+            self_ty,
+            None,
+        ));
+        remove_empty_where_clause(&mut generics);
+        generics
+    };
+
+    // Like for the existential types of trait impls, a future resolving to the wrong type is
+    // reported on the alias.
+    let span = located_at(output_span(sig));
+    let alias = ItemType {
+        attrs: iter::once(syn::parse_quote_spanned! {span=> #[doc = #alias_doc] })
+            .chain(forwarded_attributes(attrs, false))
+            .collect(),
+        eq_token: Token!(=)(span),
+        generics: alias_generics,
+        ident: alias_ident.clone(),
+        semi_token: Token!(;)(span),
+        vis: vis.clone(),
+        ty: Box::new(Type::ImplTrait(TypeImplTrait {
+            // The alias captures all of its lifetime parameters, so unlike the associated types of
+            // trait methods, it does not need any lifetime bounds.
+            bounds: iter::once(TypeParamBound::Trait(future_trait_bound(fn_output_ty)))
+                .chain(extra_bounds)
+                .collect(),
+            impl_token: Token!(impl)(span),
+        })),
+        type_token: Token!(type)(span),
+    };
+
+    let alias_path = existential_path_type(alias_ident.clone(), params);
+    sig.output = ReturnType::Type(
+        output_arrow(sig),
+        Box::new(
+            syn::parse2(respan(alias_path.into_token_stream(), span))
+                .expect("failed to parse respanned return type"),
+        ),
+    );
+    attrs.push(syn::parse_quote! {
+        #[define_opaque(#alias_ident)]
+    });
+    block.stmts = vec![Stmt::Expr(async_move_block(sig, block))];
+
+    // Lifetimes do not affect the layout, so any instantiation of the future type will do, unless
+    // it is generic over types as well.
+    let future_size_check = if has_type_params(params) {
+        None
+    } else {
+        let static_lifetimes = lifetime_path_arguments(iter::repeat_n(
+            Lifetime::new("'static", Span::call_site()),
+            params.len(),
+        ));
+        Some((
+            forwarded_attributes(attrs, false),
+            sig.ident.clone(),
+            quote! { #alias_ident #static_lifetimes },
+        ))
+    };
+    (alias, future_size_check)
+}
+
+/// A span that resolves names like the rest of the generated code, but that points at `span` in
+/// errors and for go-to-definition.
+fn located_at(span: Span) -> Span {
+    Span::call_site().located_at(span)
+}
+
+/// The span of the output type of a method, or of its name if it has none.
+fn output_span(sig: &Signature) -> Span {
+    match sig.output {
+        ReturnType::Type(_, ref ty) => ty.span(),
+        ReturnType::Default => sig.ident.span(),
+    }
+}
+
+/// The `->` of a rewritten return type, which is the one the method was written with, if any.
+fn output_arrow(sig: &Signature) -> Token![->] {
+    match sig.output {
+        ReturnType::Type(arrow, _) => arrow,
+        ReturnType::Default => Token!(->)(located_at(sig.ident.span())),
+    }
+}
+
+/// Gives all tokens the same span, so that errors in generated code point at the code that it was
+/// generated from.
+fn respan(tokens: TokenStream, span: Span) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|mut tree| {
+            if let TokenTree::Group(ref group) = tree {
+                let mut respanned = Group::new(group.delimiter(), respan(group.stream(), span));
+                respanned.set_span(span);
+                tree = respanned.into();
+            } else {
+                tree.set_span(span);
+            }
+            tree
+        })
+        .collect()
+}
+
+/// Reports all errors at once, rather than only the first one.
+fn combine_errors(errors: Vec<syn::Error>) -> Result<(), syn::Error> {
+    errors
+        .into_iter()
+        .reduce(|mut combined, error| {
+            combined.combine(error);
+            combined
+        })
+        .map_or(Ok(()), Err)
+}
+
+fn lifetime_count(generics: &Generics) -> usize {
+    generics.lifetimes().count()
+}
+
+/// Replaces generic parameters by the arguments they are given, such that types using
+/// differently named parameters compare equal, and notes projections such as `Self::Item`, whose
+/// normalized type is unknown to the macro.
+struct ParamReplacer {
+    types: Vec<(Ident, Type)>,
+    lifetimes: Vec<(Lifetime, Lifetime)>,
+    has_projection: bool,
+}
+
+impl syn::visit_mut::VisitMut for ParamReplacer {
+    fn visit_type_mut(&mut self, i: &mut Type) {
+        if let Type::Path(TypePath { qself, path }) = i {
+            let first = &path.segments[0].ident;
+            if qself.is_some()
+                || (path.segments.len() > 1
+                    && (first == "Self" || self.types.iter().any(|(param, _)| param == first)))
+            {
+                self.has_projection = true;
+            }
+            if qself.is_none() {
+                if let Some((_, arg)) = self.types.iter().find(|(param, _)| path.is_ident(param)) {
+                    *i = arg.clone();
+                    return;
+                }
+            }
+        }
+        syn::visit_mut::visit_type_mut(self, i);
+    }
+    fn visit_lifetime_mut(&mut self, i: &mut Lifetime) {
+        if let Some((_, arg)) = self.lifetimes.iter().find(|(param, _)| param.ident == i.ident) {
+            i.ident = arg.ident.clone();
+        }
+    }
+}
+
+/// The return type of a method, with its parameters replaced as given, or `None` if it contains
+/// a projection.
+fn comparable_return_type(
+    sig: &Signature,
+    types: Vec<(Ident, Type)>,
+    lifetimes: Vec<(Lifetime, Lifetime)>,
+    item: &ItemImpl,
+) -> Option<String> {
+    let mut replacer = ParamReplacer { types, lifetimes, has_projection: false };
+    let mut ty = return_type(sig.output.clone());
+    syn::visit_mut::VisitMut::visit_type_mut(&mut replacer, &mut ty);
+    if replacer.has_projection {
+        return None;
+    }
+    Some(replace_self_type(ty, &item.self_ty, None).to_token_stream().to_string())
+}
+
+/// Compares the return type of an async method with its declaration, token-wise after replacing
+/// `Self`, the parameters of the trait by the arguments of the impl, and the parameters of the
+/// impl method by the declared ones. Types that are only equal through aliases or other paths are
+/// reported as well, since the macro has no type information. Types containing projections, such
+/// as `Self::Item`, are not compared, since impls commonly name the type they resolve to instead.
+fn return_type_mismatch(
+    item: &ItemImpl,
+    trait_declaration: &ItemTrait,
+    declared: &TraitItemMethod,
+    method: &ImplItemMethod,
+) -> Option<syn::Error> {
+    let trait_args = item
+        .trait_
+        .as_ref()
+        .and_then(|(_, path, _)| match path.segments.last()?.arguments {
+            PathArguments::AngleBracketed(ref arguments) => Some(arguments.args.iter().collect::<Vec<_>>()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let trait_lifetime_args = trait_args.iter().filter_map(|arg| match arg {
+        GenericArgument::Lifetime(lifetime) => Some(lifetime.clone()),
+        _ => None,
+    });
+    let mut trait_type_args = trait_args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty.clone()),
+        _ => None,
+    });
+    // Parameters left out by the impl take their defaults.
+    let declared_types = trait_declaration
+        .generics
+        .type_params()
+        .filter_map(|param| Some((param.ident.clone(), trait_type_args.next().or_else(|| param.default.clone())?)))
+        .collect();
+    let declared_lifetimes = trait_declaration
+        .generics
+        .lifetimes()
+        .map(|def| def.lifetime.clone())
+        .zip(trait_lifetime_args)
+        .collect();
+    let impl_types = method
+        .sig
+        .generics
+        .type_params()
+        .zip(declared.sig.generics.type_params())
+        .map(|(from, to)| (from.ident.clone(), Type::Path(TypePath { qself: None, path: to.ident.clone().into() })))
+        .collect();
+    let impl_lifetimes = method
+        .sig
+        .generics
+        .lifetimes()
+        .zip(declared.sig.generics.lifetimes())
+        .map(|(from, to)| (from.lifetime.clone(), to.lifetime.clone()))
+        .collect();
+
+    let declared_ty = comparable_return_type(&declared.sig, declared_types, declared_lifetimes, item)?;
+    let impl_ty = comparable_return_type(&method.sig, impl_types, impl_lifetimes, item)?;
+    if declared_ty == impl_ty {
+        return None;
+    }
+    let span = match method.sig.output {
+        ReturnType::Type(_, ref ty) => ty.span(),
+        ReturnType::Default => method.sig.ident.span(),
+    };
+    Some(syn::Error::new(
+        span,
+        format!(
+            "`{}` returns `{}`, but its declaration in the trait `{}` returns `{}`",
+            method.sig.ident,
+            tokens_to_string(return_type(method.sig.output.clone()).to_token_stream()),
+            trait_declaration.ident,
+            tokens_to_string(return_type(declared.sig.output.clone()).to_token_stream()),
+        ),
+    ))
+}
+
+/// Compares the async methods of a trait impl with the trait declaration, so that mismatches are
+/// reported on the impl instead of as errors about the generated associated types.
+fn check_against_declaration(item: &ItemImpl, trait_declaration: &ItemTrait) -> Result<(), syn::Error> {
+    let trait_ident = &trait_declaration.ident;
+    let declared_methods = trait_declaration
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Method(method) => Some(method),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let impl_methods = item
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            ImplItem::Method(method) => Some(method),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+
+    for method in &impl_methods {
+        let ident = &method.sig.ident;
+        let declared = declared_methods.iter().find(|declared| declared.sig.ident == *ident);
+
+        match declared {
+            None if method.sig.asyncness.is_some() => errors.push(syn::Error::new(
+                ident.span(),
+                format!("`{}` is not a method of the trait `{}`", ident, trait_ident),
+            )),
+            None => (),
+            Some(declared) => match (declared.sig.asyncness.is_some(), method.sig.asyncness.is_some()) {
+                (true, false) => errors.push(syn::Error::new(
+                    method.sig.fn_token.span,
+                    format!("`{}` is declared as `async fn` in the trait `{}`, but is not async here", ident, trait_ident),
+                )),
+                (false, true) => errors.push(syn::Error::new(
+                    method.sig.asyncness.span(),
+                    format!("`{}` is not declared as `async fn` in the trait `{}`", ident, trait_ident),
+                )),
+                (true, true) if lifetime_count(&declared.sig.generics) != lifetime_count(&method.sig.generics) => {
+                    let span = if method.sig.generics.params.is_empty() {
+                        ident.span()
+                    } else {
+                        method.sig.generics.span()
+                    };
+                    errors.push(syn::Error::new(
+                        span,
+                        format!(
+                            "`{}` has {} lifetime parameter(s), but its declaration in the trait `{}` has {}",
+                            ident,
+                            lifetime_count(&method.sig.generics),
+                            trait_ident,
+                            lifetime_count(&declared.sig.generics),
+                        ),
+                    ));
+                }
+                (true, true) if declared.sig.unsafety.is_some() != method.sig.unsafety.is_some() => {
+                    errors.push(syn::Error::new(
+                        method.sig.unsafety.map_or(method.sig.fn_token.span, |unsafety| unsafety.span),
+                        format!(
+                            "`{}` is {}declared as `unsafe` in the trait `{}`, but is {}unsafe here",
+                            ident,
+                            if declared.sig.unsafety.is_some() { "" } else { "not " },
+                            trait_ident,
+                            if method.sig.unsafety.is_some() { "" } else { "not " },
+                        ),
+                    ));
+                }
+                (true, true) => errors.extend(return_type_mismatch(item, trait_declaration, declared, method)),
+                _ => (),
+            },
+        }
+    }
+
+    let missing = declared_methods
+        .iter()
+        .filter(|declared| declared.sig.asyncness.is_some() && declared.default.is_none())
+        .filter(|declared| !impl_methods.iter().any(|method| method.sig.ident == declared.sig.ident))
+        .map(|declared| format!("`{}`", declared.sig.ident))
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        let span = item.trait_.as_ref().map_or_else(|| item.self_ty.span(), |(_, path, _)| path.span());
+        errors.push(syn::Error::new(
+            span,
+            format!("missing async method(s) of the trait `{}`: {}", trait_ident, missing.join(", ")),
+        ));
+    }
+
+    combine_errors(errors)
+}
+
+/// The attributes that an async method was declared with in a trait, including `boxed` for
+/// provided methods.
+fn declared_attributes(trait_declaration: &ItemTrait, ident: &Ident) -> HashSet<RealAsyncTraitAttributes> {
+    trait_declaration
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Method(method) if method.sig.ident == *ident && method.sig.asyncness.is_some() => {
+                Some(method)
+            }
+            _ => None,
+        })
+        .flat_map(|method| {
+            // Errors are reported by the expansion of the trait.
+            let mut attributes = parse_attributes(&mut method.attrs.clone()).unwrap_or_default();
+            if method.default.is_some() {
+                attributes.insert(RealAsyncTraitAttributes::Boxed);
+            }
+            attributes
+        })
+        .collect()
+}
+
+/// Documentation appended to each async method of a trait, since rustdoc only sees the downgraded
+/// signature returning a generated associated type.
+fn async_signature_doc(signature: &Signature, output_ty: &Type, is_send: bool) -> Vec<Attribute> {
+    let description = format!(
+        "This method is declared as `{}`, and the future it returns resolves to `{}`{}.",
+        tokens_to_string(quote! { #signature }),
+        tokens_to_string(quote! { #output_ty }),
+        if is_send { " and is `Send`" } else { "" },
+    );
+    vec![
+        syn::parse_quote! { #[doc = ""] },
+        syn::parse_quote! { #[doc = #description] },
+    ]
+}
+
+/// Renders tokens roughly the way rustfmt would, which is considerably more readable in docs than
+/// the `to_string` output of a token stream.
+fn tokens_to_string(tokens: TokenStream) -> String {
+    use proc_macro2::{Delimiter, Spacing, TokenTree};
+
+    // Glue joint punctuation (`::`, `->`, `'a`) together, so that spacing can be decided for
+    // whole operators.
+    let mut pieces = Vec::new();
+    let mut joint = String::new();
+    for tree in tokens {
+        match tree {
+            TokenTree::Punct(punct) if punct.spacing() == Spacing::Joint => {
+                joint.push(punct.as_char());
+            }
+            TokenTree::Punct(punct) => {
+                joint.push(punct.as_char());
+                pieces.push(mem::take(&mut joint));
+            }
+            TokenTree::Ident(ident) => pieces.push(mem::take(&mut joint) + &ident.to_string()),
+            TokenTree::Literal(literal) => pieces.push(mem::take(&mut joint) + &literal.to_string()),
+            TokenTree::Group(group) => {
+                let inner = tokens_to_string(group.stream());
+                let group = match group.delimiter() {
+                    Delimiter::Parenthesis => format!("({})", inner),
+                    Delimiter::Bracket => format!("[{}]", inner),
+                    Delimiter::Brace => format!("{{ {} }}", inner),
+                    Delimiter::None => inner,
+                };
+                pieces.push(mem::take(&mut joint) + &group);
+            }
+        }
+    }
+
+    let mut string = String::new();
+    let mut previous: Option<&str> = None;
+    for piece in pieces.iter() {
+        let space = match previous {
+            None => false,
+            Some(previous) => {
+                let no_space_after = matches!(previous, "&" | "<" | "::" | "." | "#" | "!" | "*");
+                let no_space_before = matches!(piece.as_str(), "," | ";" | ":" | "::" | ">" | "." | "?")
+                    || piece.starts_with('(')
+                        && previous != "->"
+                        && previous.chars().last().is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '>')
+                    || piece == "<"
+                        && previous.chars().last().is_some_and(|c| c.is_alphanumeric() || c == '_');
+                !(no_space_after || no_space_before)
+            }
+        };
+        if space {
+            string.push(' ');
+        }
+        string.push_str(piece);
+        previous = Some(piece);
+    }
+    string
+}
+
+/// The attributes of an async method that have to be applied to the types generated for it as
+/// well, most importantly `#[cfg]`, so that a disabled method does not leave its types behind.
+///
+/// `#[deprecated]` is only forwarded to the associated types of a trait, by the trait expansion.
+/// Naming a deprecated type in a signature triggers the lint even within a deprecated item, so
+/// every other generated item that names one has to avoid it or allow the lint.
+fn forwarded_attributes(attrs: &[Attribute], include_docs: bool) -> Vec<Attribute> {
+    const FORWARDED_ATTRIBUTES: &[&str] = &[
+        "cfg", "cfg_attr", "allow", "warn", "deny", "forbid", "expect",
+    ];
+
+    attrs
+        .iter()
+        .filter(|attr| {
+            FORWARDED_ATTRIBUTES
+                .iter()
+                .any(|forwarded| attr.path.is_ident(forwarded))
+                || (include_docs && attr.path.is_ident("doc"))
+        })
+        .cloned()
+        .collect()
+}
+
+fn is_deprecated(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| attr.path.is_ident("deprecated"))
+}
+
+/// Wraps the body of an async fn in an async block, which takes ownership of all arguments in the
+/// same way as a native async fn, so that they are dropped at the same time, even if the body never
+/// mentions them. As in the lowering of the compiler, arguments other than simple bindings are
+/// renamed, and then destructured within the async block.
+fn async_move_block(signature: &mut Signature, block: &mut Block) -> Expr {
+    let mut bindings: Vec<Stmt> = Vec::new();
+
+    for (index, input) in signature.inputs.iter_mut().enumerate() {
+        match input {
+            // The receiver cannot be rebound, but mentioning it is enough to move it into the
+            // future.
+            // `self` has to be spanned like the receiver to resolve to it.
+            FnArg::Receiver(Receiver { reference: None, self_token, .. }) => {
+                bindings.push(syn::parse_quote! { let _ = &#self_token; });
+            }
+            FnArg::Receiver(_) => (),
+            // The same goes for typed receivers such as `self: Box<Self>`.
+            FnArg::Typed(PatType { ref pat, .. }) if is_self_pat(pat) => {
+                if let Pat::Ident(PatIdent { ref ident, .. }) = **pat {
+                    bindings.push(syn::parse_quote! { let _ = &#ident; });
+                }
+            }
+            FnArg::Typed(PatType { ref mut pat, .. }) => match **pat {
+                Pat::Ident(PatIdent {
+                    by_ref: None,
+                    ref mut mutability,
+                    ref ident,
+                    subpat: None,
+                    ..
+                }) => {
+                    let mutability = mutability.take();
+                    bindings.push(syn::parse_quote! { let #mutability #ident = #ident; });
+                }
+                _ => {
+                    let arg_ident = quote::format_ident!("__real_async_trait_impl_arg{}", index);
+                    let arg_pat = mem::replace(&mut **pat, syn::parse_quote! { #arg_ident });
+                    bindings.push(syn::parse_quote! { let #arg_ident = #arg_ident; });
+                    bindings.push(syn::parse_quote! { let #arg_pat = #arg_ident; });
+                }
+            },
+        }
+    }
+
+    // Errors about the future as a whole, such as it resolving to the wrong type or not being
+    // `Send`, point at the body.
+    let span = located_at(block.brace_token.span);
+    let stmts = mem::take(&mut block.stmts);
+
+    Expr::Async(ExprAsync {
+        async_token: Token!(async)(span),
+        attrs: Vec::new(),
+        block: Block {
+            brace_token: token::Brace { span },
+            stmts: bindings.into_iter().chain(stmts).collect(),
+        },
+        capture: Some(Token!(move)(span)),
+    })
+}
+
+/// The type returned by async methods that are boxed rather than using a GAT, i.e.
+/// `Pin<Box<dyn Future<Output = T> + 'a>>`.
+fn boxed_future_type(fn_output_ty: Type, bounds: Vec<TypeParamBound>) -> Type {
+    let future_trait_bound = future_trait_bound(fn_output_ty);
+
+    syn::parse2(quote! {
+        ::core::pin::Pin<::std::boxed::Box<dyn #future_trait_bound #(+ #bounds)*>>
+    })
+    .expect("failed to parse boxed future type")
+}
+/// `Box::pin(future)`, where a future resolving to the wrong type is reported, spanned like the
+/// output type at `span`.
+fn boxed_future_expr(future: Expr, span: Span) -> Expr {
+    syn::parse2(quote_spanned! {located_at(span)=> ::std::boxed::Box::pin(#future) })
+        .expect("failed to parse `Box::pin` expression")
+}
+
+fn return_type(retval: ReturnType) -> Type {
+    match retval {
+        ReturnType::Default => Type::Tuple(TypeTuple {
+            elems: Punctuated::new(),
+            paren_token: token::Paren {
+                span: Span::call_site(),
+            },
+        }),
+        ReturnType::Type(_, ty) => *ty,
+    }
+}
+
+fn future_trait_bound(fn_output_ty: Type) ->TraitBound {
+    const FUTURE_TRAIT_PATH_STR: &str = "::core::future::Future";
+    const FUTURE_TRAIT_OUTPUT_IDENT_STR: &str = "Output";
+
+    // An output type that the future does not resolve to is reported on the bound, which should
+    // then point at the output type.
+    let span = located_at(fn_output_ty.span());
+
+    let mut future_trait_path =
+        syn::parse2::<Path>(respan(TokenStream::from_str(FUTURE_TRAIT_PATH_STR).unwrap(), span))
+            .expect("failed to parse `::core::future::Future` as a syn `Path`");
+
+    let future_angle_bracketed_args = AngleBracketedGenericArguments {
+        colon2_token: None, // FIXME
+        lt_token: Token!(<)(span),
+        gt_token: Token!(>)(span),
+        args: iter::once(GenericArgument::Binding(Binding {
+            ident: Ident::new(FUTURE_TRAIT_OUTPUT_IDENT_STR, span),
+            eq_token: Token!(=)(span),
+            ty: fn_output_ty,
+        }))
+        .collect(),
     };
 
     future_trait_path
@@ -370,207 +2007,1420 @@ fn future_trait_bound(fn_output_ty: Type) ->TraitBound {
         .expect("Expected ::core::future::Future to have `Future` as the last segment")
         .arguments = PathArguments::AngleBracketed(future_angle_bracketed_args);
 
-    use quote::ToTokens;
-    
-    let mut  test_tok_stream: TokenStream =TokenStream::new();
-    future_trait_path.to_tokens(&mut test_tok_stream);
-    println!("[{}:{}] {}",file!(), line!(), test_tok_stream.to_string());
+    TraitBound {
+        // for TraitBounds, these are HRTBs, which are useless since there are already GATs present
+        lifetimes: None,
+        // This is not ?Sized or something like that
+        modifier: TraitBoundModifier::None,
+        paren_token: None,
+        path: future_trait_path,
+    }
+}
+
+/// A path to an existential type alias of an impl, e.g.
+/// `self::__real_async_trait_impl_ExistentialTypeFor_open<'a>`.
+fn existential_path_type(ident: Ident, params: &[GenericParam]) -> Type {
+    Type::Path(TypePath {
+        path: Path {
+            leading_colon: None,
+            segments: vec![
+                PathSegment {
+                    arguments: PathArguments::None,
+                    ident: Ident::new("self", located_at(ident.span())),
+                },
+                PathSegment {
+                    arguments: param_path_arguments(params),
+                    ident,
+                },
+            ]
+            .into_iter()
+            .collect(),
+        },
+        qself: None,
+    })
+}
+
+/// The name of the type generated for the `index`th `impl Trait` in the output of a method.
+fn nested_type_ident(ident: &Ident, index: usize) -> Ident {
+    Ident::new(&format!("{}_{}", ident, index), ident.span())
+}
+
+/// Replaces every `impl Trait` within a type, innermost first, with a type chosen by the
+/// replacement function from the index of the `impl Trait`, collecting the bounds of each.
+struct ImplTraitReplacer<F> {
+    replacement: F,
+    bounds: Vec<Punctuated<TypeParamBound, Token![+]>>,
+}
+
+impl<F: FnMut(usize) -> Type> syn::visit_mut::VisitMut for ImplTraitReplacer<F> {
+    fn visit_type_mut(&mut self, i: &mut Type) {
+        syn::visit_mut::visit_type_mut(self, i);
+
+        if let Type::ImplTrait(TypeImplTrait { bounds, .. }) = i {
+            self.bounds.push(mem::take(bounds));
+            *i = (self.replacement)(self.bounds.len() - 1);
+        }
+    }
+}
+
+fn replace_nested_impl_traits(
+    ty: &mut Type,
+    replacement: impl FnMut(usize) -> Type,
+) -> Vec<Punctuated<TypeParamBound, Token![+]>> {
+    let mut replacer = ImplTraitReplacer {
+        replacement,
+        bounds: Vec::new(),
+    };
+    syn::visit_mut::VisitMut::visit_type_mut(&mut replacer, ty);
+    replacer.bounds
+}
+
+fn returns_impl_trait(signature: &Signature) -> bool {
+    matches!(signature.output, ReturnType::Type(_, ref ty) if matches!(**ty, Type::ImplTrait(_)))
+}
+
+/// The bounds of the type returned by a lowered method: `Future<Output = T> + 'a` for async
+/// methods, and the bounds of `impl Bounds` for methods returning `impl Trait`.
+fn returned_type_bounds(
+    signature: &Signature,
+    is_async: bool,
+    fn_output_ty: Type,
+    params: &[GenericParam],
+) -> Vec<TypeParamBound> {
+    if !is_async {
+        if let Type::ImplTrait(TypeImplTrait { bounds, .. }) = fn_output_ty {
+            return bounds.into_iter().collect();
+        }
+    }
+    iter::once(TypeParamBound::Trait(future_trait_bound(fn_output_ty)))
+        .chain(future_lifetime_bounds(signature, params))
+        .collect()
+}
+
+/// The additional bound that an attribute puts on the future type, if any, spanned like the output
+/// type at `span`, which is where a future that is not `Send` is reported.
+fn send_bound(attr: RealAsyncTraitAttributes, span: Span) -> Option<TypeParamBound> {
+    const SEND_TRAIT_PATH_STR: &str = "::core::marker::Send";
+    match attr {
+        RealAsyncTraitAttributes::Send => {
+            let tokens = respan(TokenStream::from_str(SEND_TRAIT_PATH_STR).unwrap(), located_at(span));
+            let path = syn::parse2::<Path>(tokens)
+                .unwrap_or_else(|_| panic!("Failed to parse {} into path", SEND_TRAIT_PATH_STR));
+            Some(TypeParamBound::Trait(TraitBound {
+                lifetimes: None,
+                modifier: TraitBoundModifier::None,
+                paren_token: None,
+                path,
+            }))
+        }
+        RealAsyncTraitAttributes::Boxed => None,
+    }
+}
+
+/// Whether a bound is `Send`, as far as can be told from its path.
+fn is_send_bound(bound: &TypeParamBound) -> bool {
+    let path = match bound {
+        TypeParamBound::Trait(TraitBound { path, lifetimes: None, modifier: TraitBoundModifier::None, .. }) => path,
+        _ => return false,
+    };
+    let segments = path.segments.iter().map(|segment| segment.ident.to_string()).collect::<Vec<_>>();
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+    path.segments.iter().all(|segment| segment.arguments.is_empty())
+        && matches!(
+            segments.as_slice(),
+            ["Send"] | ["core", "marker", "Send"] | ["std", "marker", "Send"]
+        )
+}
+
+/// Checks everything about a signature that the lowering relies on, up front.
+fn validate_signature(signature: &Signature) -> Result<(), syn::Error> {
+    let mut errors = Vec::new();
+    if let Err(error) = validate_qualifiers(signature) {
+        errors.push(error);
+    }
+    if let Err(error) = validate_that_function_always_has_lifetimes(signature) {
+        errors.push(error);
+    }
+    combine_errors(errors)
+}
+
+/// `unsafe` is kept on the lowered fn, and since the async block inherits its unsafe context,
+/// `unsafe_op_in_unsafe_fn` applies to the body exactly like it would to a native async fn. `const`
+/// and an explicit ABI have no meaning for a fn that returns a named future, and are rejected.
+fn validate_qualifiers(signature: &Signature) -> Result<(), syn::Error> {
+    if signature.asyncness.is_none() {
+        return Ok(());
+    }
+    let mut errors = Vec::new();
+    if let Some(constness) = signature.constness {
+        errors.push(syn::Error::new(
+            constness.span,
+            format!("`{}` cannot be both `const` and `async`", signature.ident),
+        ));
+    }
+    if let Some(ref abi) = signature.abi {
+        errors.push(syn::Error::new_spanned(
+            abi,
+            format!("`{}` cannot have an explicit ABI, which is not supported for async fns by this proc macro", signature.ident),
+        ));
+    }
+    combine_errors(errors)
+}
+
+fn validate_that_function_always_has_lifetimes(signature: &Signature) -> Result<(), syn::Error> {
+    let mut visitor = LifetimeVisitor { errors: Vec::new() };
+    for input in signature.inputs.iter() {
+        match input {
+            FnArg::Receiver(ref recv) => {
+                if let Some((ampersand, None)) = recv.reference {
+                    visitor.errors.push(syn::Error::new(
+                        ampersand.span,
+                        format!("{}self parameter lacked an explicit lifetime, which is required by this proc macro", if recv.mutability.is_some() { "&mut " } else { "&" }),
+                    ));
+                }
+            }
+            FnArg::Typed(PatType { ref ty, .. }) => {
+                syn::visit::visit_type(&mut visitor, ty)
+            }
+        }
+    }
+    if let ReturnType::Type(_, ref ty) = signature.output {
+        syn::visit::visit_type(&mut visitor, ty);
+    };
+    combine_errors(visitor.errors)
+}
+
+/// How a method takes its receiver, which decides what the returned future may borrow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReceiverKind {
+    /// An associated function without a `self` parameter.
+    None,
+    /// `&'a self`, `&'a mut self`, `self: &'a Self`, `self: Pin<&'a mut Self>` and the like.
+    Borrowed,
+    /// `self`, `mut self`, `self: Box<Self>`, `self: Arc<Self>` and the like.
+    Owned,
+}
+
+struct ReferenceVisitor(bool);
+
+impl<'ast> syn::visit::Visit<'ast> for ReferenceVisitor {
+    fn visit_type_reference(&mut self, _: &'ast TypeReference) {
+        self.0 = true;
+    }
+}
+
+fn receiver_kind(signature: &Signature) -> ReceiverKind {
+    match signature.inputs.first() {
+        Some(FnArg::Receiver(recv)) if recv.reference.is_some() => ReceiverKind::Borrowed,
+        Some(FnArg::Receiver(_)) => ReceiverKind::Owned,
+        Some(FnArg::Typed(PatType { pat, ty, .. })) if is_self_pat(pat) => {
+            // `self: Pin<&'a mut Self>` borrows, whereas `self: Box<Self>` or `self: Arc<Self>`
+            // moves the (smart pointer to) `Self` into the future.
+            let mut visitor = ReferenceVisitor(false);
+            syn::visit::visit_type(&mut visitor, ty);
+            if visitor.0 {
+                ReceiverKind::Borrowed
+            } else {
+                ReceiverKind::Owned
+            }
+        }
+        _ => ReceiverKind::None,
+    }
+}
+fn is_self_pat(pat: &Pat) -> bool {
+    matches!(pat, Pat::Ident(PatIdent { ident, .. }) if ident == "self")
+}
+/// The lifetimes that the future type of a method must outlive. Futures of methods that take
+/// `self` by value own `Self`, and thus cannot be bounded by any lifetime without implicitly
+/// requiring `Self: 'a`.
+fn future_lifetime_bounds(signature: &Signature, params: &[GenericParam]) -> Vec<TypeParamBound> {
+    if receiver_kind(signature) == ReceiverKind::Owned {
+        return Vec::new();
+    }
+    params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Lifetime(lifetime_def) => Some(TypeParamBound::Lifetime(lifetime_def.lifetime.clone())),
+            _ => None,
+        })
+        .collect()
+}
+/// Collects the outlives bounds implied by references in a method signature, e.g. `Self: 'a` for
+/// `&'a self` or `T: 'a` for `&'a T`. GATs must state these explicitly in their where clauses.
+struct OutlivesVisitor<'a> {
+    type_params: Vec<&'a Ident>,
+    gat_params: &'a [GenericParam],
+    predicates: Vec<(Ident, Lifetime)>,
+}
+
+impl<'a> OutlivesVisitor<'a> {
+    fn push(&mut self, ident: Ident, lifetime: &Lifetime) {
+        let is_gat_lifetime = self.gat_params.iter().any(|param| {
+            matches!(param, GenericParam::Lifetime(lifetime_def) if lifetime_def.lifetime == *lifetime)
+        });
+        let predicate = (ident, lifetime.clone());
+
+        if is_gat_lifetime && !self.predicates.contains(&predicate) {
+            self.predicates.push(predicate);
+        }
+    }
+}
+
+impl<'a, 'ast> syn::visit::Visit<'ast> for OutlivesVisitor<'a> {
+    fn visit_type_reference(&mut self, i: &'ast TypeReference) {
+        if let Some(ref lifetime) = i.lifetime {
+            if mentions_self_type(&i.elem) {
+                self.push(Ident::new("Self", Span::call_site()), lifetime);
+            }
+            let mentioned_params = self
+                .type_params
+                .iter()
+                .filter(|ident| mentions_type_param(&i.elem, ident))
+                .map(|ident| (*ident).clone())
+                .collect::<Vec<_>>();
+            for ident in mentioned_params {
+                self.push(ident, lifetime);
+            }
+        }
+        syn::visit::visit_type_reference(self, i);
+    }
+}
+
+struct TypeParamVisitor<'a>(&'a Ident, bool);
+
+impl<'a, 'ast> syn::visit::Visit<'ast> for TypeParamVisitor<'a> {
+    fn visit_type_path(&mut self, i: &'ast TypePath) {
+        if i.qself.is_none() && i.path.is_ident(self.0) {
+            self.1 = true;
+        }
+        syn::visit::visit_type_path(self, i);
+    }
+}
+
+fn mentions_type_param(ty: &Type, ident: &Ident) -> bool {
+    let mut visitor = TypeParamVisitor(ident, false);
+    syn::visit::visit_type(&mut visitor, ty);
+    visitor.1
+}
+fn gat_outlives_predicates(
+    signature: &Signature,
+    toplevel_generics: &Generics,
+    function_params: &[GenericParam],
+) -> Vec<WherePredicate> {
+    let mut visitor = OutlivesVisitor {
+        type_params: toplevel_generics
+            .type_params()
+            .chain(function_params.iter().filter_map(|param| match param {
+                GenericParam::Type(type_param) => Some(type_param),
+                _ => None,
+            }))
+            .map(|param| &param.ident)
+            .collect(),
+        gat_params: function_params,
+        predicates: Vec::new(),
+    };
+    for input in signature.inputs.iter() {
+        match input {
+            FnArg::Receiver(Receiver {
+                reference: Some((_, Some(lifetime))),
+                ..
+            }) => visitor.push(Ident::new("Self", Span::call_site()), lifetime),
+            FnArg::Receiver(_) => (),
+            FnArg::Typed(PatType { ty, .. }) => syn::visit::visit_type(&mut visitor, ty),
+        }
+    }
+    visitor
+        .predicates
+        .into_iter()
+        .map(|(ident, lifetime)| syn::parse_quote! { #ident: #lifetime })
+        .collect()
+}
+#[derive(Default)]
+struct ImpliedOutlivesVisitor {
+    lifetimes: Vec<Lifetime>,
+    predicates: Vec<(Lifetime, Lifetime)>,
+}
+
+impl<'ast> syn::visit::Visit<'ast> for ImpliedOutlivesVisitor {
+    fn visit_lifetime(&mut self, i: &'ast Lifetime) {
+        self.lifetimes.push(i.clone());
+    }
+    fn visit_type_reference(&mut self, i: &'ast TypeReference) {
+        let outer_lifetimes = mem::take(&mut self.lifetimes);
+        syn::visit::visit_type(self, &i.elem);
+        if let Some(lifetime) = i.lifetime.as_ref().filter(|_| !mentions_self_type(&i.elem)) {
+            for inner in self.lifetimes.iter() {
+                let predicate = (inner.clone(), lifetime.clone());
+                if inner != lifetime && inner.ident != "static" && !self.predicates.contains(&predicate) {
+                    self.predicates.push(predicate);
+                }
+            }
+            self.lifetimes.push(lifetime.clone());
+        }
+        let inner_lifetimes = mem::replace(&mut self.lifetimes, outer_lifetimes);
+        self.lifetimes.extend(inner_lifetimes);
+    }
+}
+
+/// The outlives bounds between lifetimes that the inputs of a function imply, e.g. `'b: 'a` for
+/// `&'a Cursor<'b>`. Type aliases do not get implied bounds, so they have to be spelled out on the
+/// alias of a named future. References to `Self` are covered by `Self: 'a` bounds instead.
+fn implied_outlives_predicates(signature: &Signature) -> Vec<WherePredicate> {
+    let mut visitor = ImpliedOutlivesVisitor::default();
+    for input in signature.inputs.iter() {
+        if let FnArg::Typed(PatType { ref ty, .. }) = input {
+            syn::visit::visit_type(&mut visitor, ty);
+        }
+    }
+    visitor
+        .predicates
+        .into_iter()
+        .map(|(inner, outer)| syn::parse_quote! { #inner: #outer })
+        .collect()
+}
+fn remove_empty_where_clause(generics: &mut Generics) {
+    if generics
+        .where_clause
+        .as_ref()
+        .is_some_and(|where_clause| where_clause.predicates.is_empty())
+    {
+        generics.where_clause = None;
+    }
+}
+/// The generic parameters in scope of a method, lifetimes first, which all become parameters of
+/// the existential types. The second list only contains those of the method itself, which are the
+/// parameters of the generic associated types.
+fn already_defined_params(
+    toplevel_generics: &Generics,
+    method_generics: &Generics,
+) -> (Vec<GenericParam>, Vec<GenericParam>) {
+    //Global scope
+    //let mut lifetimes = vec! [LifetimeDef::new(Lifetime::new("'static", Span::call_site()))];
+
+    let mut params = Vec::new();
+    // Trait definition scope
+    params.extend(toplevel_generics.lifetimes().cloned().map(GenericParam::Lifetime));
+    // Function definition scope
+    let function_params = method_generics
+        .lifetimes()
+        .cloned()
+        .map(GenericParam::Lifetime)
+        .chain(method_generics.type_params().cloned().map(GenericParam::Type))
+        .collect::<Vec<_>>();
+    // Lifetimes have to come before the other parameters.
+    params.extend(method_generics.lifetimes().cloned().map(GenericParam::Lifetime));
+    params.extend(
+        toplevel_generics
+            .params
+            .iter()
+            .filter(|param| !matches!(param, GenericParam::Lifetime(_)))
+            .cloned(),
+    );
+    params.extend(method_generics.type_params().cloned().map(GenericParam::Type));
+    (params, function_params)
+}
+
+/// Whether the generated types are generic over types or constants, which rules out computing the
+/// sizes of their futures.
+fn has_type_params(params: &[GenericParam]) -> bool {
+    params.iter().any(|param| !matches!(param, GenericParam::Lifetime(_)))
+}
+
+fn where_predicates(generics: &Generics) -> impl Iterator<Item = WherePredicate> + '_ {
+    generics
+        .where_clause
+        .iter()
+        .flat_map(|where_clause| where_clause.predicates.iter().cloned())
+}
+
+/// The where clauses that an existential type outside of an impl needs, i.e. those of the impl and
+/// of the method, and the ones implied by the inputs of the method, in terms of the implementing
+/// type rather than `Self`.
+fn existential_where_predicates(
+    toplevel_generics: &Generics,
+    signature: &Signature,
+    function_params: &[GenericParam],
+    self_ty: Option<&Type>,
+    trait_path: Option<&Path>,
+) -> Vec<WherePredicate> {
+    // Without any parameters of the impl, the implementing type outlives any lifetime.
+    let is_generic_impl = !toplevel_generics.params.is_empty();
+
+    gat_outlives_predicates(signature, toplevel_generics, function_params)
+        .into_iter()
+        .filter(|predicate| match predicate {
+            WherePredicate::Type(predicate_type) => is_generic_impl || !is_self_type(&predicate_type.bounded_ty),
+            _ => true,
+        })
+        .chain(where_predicates(toplevel_generics))
+        .chain(where_predicates(&signature.generics))
+        .map(|mut predicate| {
+            if let Some(self_ty) = self_ty {
+                syn::visit_mut::VisitMut::visit_where_predicate_mut(
+                    &mut SelfTypeReplacer { self_ty, trait_path },
+                    &mut predicate,
+                );
+            }
+            predicate
+        })
+        .collect()
+}
+
+/// Replaces `impl Trait` argument types with type parameters of the method, which the generated
+/// types can then be generic over. The trait and its impls name them the same way.
+fn desugar_impl_trait_arguments(signature: &mut Signature) {
+    let mut type_params = Vec::new();
+
+    for input in signature.inputs.iter_mut() {
+        if let FnArg::Typed(PatType { ref mut ty, .. }) = input {
+            let offset = type_params.len();
+            let bounds = replace_nested_impl_traits(ty, |index| {
+                let ident = impl_argument_ident(offset + index);
+                syn::parse_quote! { #ident }
+            });
+            type_params.extend(bounds.into_iter().enumerate().map(|(index, bounds)| {
+                let ident = impl_argument_ident(offset + index);
+                GenericParam::Type(syn::parse_quote! { #ident: #bounds })
+            }));
+        }
+    }
+    if !type_params.is_empty() {
+        signature.generics.lt_token.get_or_insert_with(Default::default);
+        signature.generics.gt_token.get_or_insert_with(Default::default);
+        signature.generics.params.extend(type_params);
+    }
+}
+
+fn impl_argument_ident(index: usize) -> Ident {
+    quote::format_ident!("__RealAsyncTraitImplArg{}", index)
+}
+/// Generic arguments for a path to a generated type, e.g. `<'a>` in `Self::TypeFor_open<'a>`, or no
+/// angle brackets at all if there are no lifetimes.
+fn lifetime_path_arguments(lifetimes: impl IntoIterator<Item = Lifetime>) -> PathArguments {
+    generic_path_arguments(lifetimes.into_iter().map(GenericArgument::Lifetime))
+}
+fn param_path_arguments(params: &[GenericParam]) -> PathArguments {
+    generic_path_arguments(params.iter().map(|param| match param {
+        GenericParam::Lifetime(lifetime_def) => GenericArgument::Lifetime(lifetime_def.lifetime.clone()),
+        GenericParam::Type(type_param) => {
+            let ident = &type_param.ident;
+            GenericArgument::Type(syn::parse_quote! { #ident })
+        }
+        GenericParam::Const(const_param) => {
+            let ident = &const_param.ident;
+            GenericArgument::Const(syn::parse_quote! { #ident })
+        }
+    }))
+}
+fn generic_path_arguments(args: impl IntoIterator<Item = GenericArgument>) -> PathArguments {
+    let args: Punctuated<GenericArgument, Token![,]> = args.into_iter().collect();
+
+    if args.is_empty() {
+        return PathArguments::None;
+    }
+    PathArguments::AngleBracketed(AngleBracketedGenericArguments {
+        colon2_token: None,
+        lt_token: Token!(<)(Span::call_site()),
+        gt_token: Token!(>)(Span::call_site()),
+        args,
+    })
+}
+/// Generic parameters for a generated type, omitting the angle brackets if there are no lifetimes.
+fn param_generics(params: &[GenericParam]) -> Generics {
+    let params: Punctuated<GenericParam, Token![,]> = params.iter().cloned().collect();
+
+    Generics {
+        lt_token: if params.is_empty() { None } else { Some(Token!(<)(Span::call_site())) },
+        gt_token: if params.is_empty() { None } else { Some(Token!(>)(Span::call_site())) },
+        params,
+        where_clause: None,
+    }
+}
+struct SelfTypeVisitor(bool);
+
+impl<'ast> syn::visit::Visit<'ast> for SelfTypeVisitor {
+    fn visit_type_path(&mut self, i: &'ast TypePath) {
+        if i.qself.is_none() && i.path.is_ident("Self") {
+            self.0 = true;
+        }
+        syn::visit::visit_type_path(self, i);
+    }
+}
+
+fn is_self_type(ty: &Type) -> bool {
+    matches!(ty, Type::Path(TypePath { qself: None, path }) if path.is_ident("Self"))
+}
+fn mentions_self_type(ty: &Type) -> bool {
+    let mut visitor = SelfTypeVisitor(false);
+    syn::visit::visit_type(&mut visitor, ty);
+    visitor.0
+}
+fn self_sized_predicate() -> WherePredicate {
+    syn::parse2(quote! { Self: ::core::marker::Sized })
+        .expect("failed to parse `Self: ::core::marker::Sized` as a where predicate")
+}
+
+/// Replaces `Self` with the self type of an impl block, so that types from method signatures can
+/// be used outside of the impl, e.g. in the existential type aliases.
+struct SelfTypeReplacer<'a> {
+    self_ty: &'a Type,
+    trait_path: Option<&'a Path>,
+}
+
+impl<'a> syn::visit_mut::VisitMut for SelfTypeReplacer<'a> {
+    fn visit_type_mut(&mut self, i: &mut Type) {
+        if let Type::Path(TypePath { qself: None, path }) = i {
+            if path.is_ident("Self") {
+                *i = self.self_ty.clone();
+                return;
+            }
+            if path.leading_colon.is_none()
+                && path.segments.len() > 1
+                && path.segments[0].ident == "Self"
+                && path.segments[0].arguments.is_empty()
+            {
+                // Self::Assoc becomes <SelfTy as Trait>::Assoc, or <SelfTy>::Assoc for inherent
+                // impls.
+                let trait_segments = self
+                    .trait_path
+                    .map(|trait_path| trait_path.segments.clone())
+                    .unwrap_or_default();
+                let position = trait_segments.len();
+                let segments = trait_segments
+                    .into_iter()
+                    .chain(path.segments.iter().skip(1).cloned())
+                    .collect();
+
+                *i = Type::Path(TypePath {
+                    qself: Some(QSelf {
+                        lt_token: Token!(<)(Span::call_site()),
+                        ty: Box::new(self.self_ty.clone()),
+                        position,
+                        as_token: if position > 0 {
+                            Some(Token!(as)(Span::call_site()))
+                        } else {
+                            None
+                        },
+                        gt_token: Token!(>)(Span::call_site()),
+                    }),
+                    path: Path {
+                        leading_colon: self.trait_path.and_then(|path| path.leading_colon),
+                        segments,
+                    },
+                });
+            }
+        }
+        syn::visit_mut::visit_type_mut(self, i);
+    }
+}
+
+fn replace_self_type(mut ty: Type, self_ty: &Type, trait_path: Option<&Path>) -> Type {
+    syn::visit_mut::VisitMut::visit_type_mut(&mut SelfTypeReplacer { self_ty, trait_path }, &mut ty);
+    ty
+}
+/// The associated type of an async method, which points at the method for go-to-definition.
+fn gat_ident_for_sig(sig: &Signature) -> Ident {
+    let gat_name = format!("__real_async_trait_impl_TypeFor_{}", sig.ident);
+    Ident::new(&gat_name, located_at(sig.ident.span()))
+}
+/// The `Self::__real_async_trait_impl_TypeFor_x<..>` that replaces the output type at `span`.
+fn self_gat_type(gat_ident: Ident, function_params: &[GenericParam], span: Span) -> TypePath {
+    TypePath {
+        path: Path {
+            // represents the pattern Self::GAT_NAME...
+            leading_colon: None,
+            segments: vec![
+                PathSegment {
+                    ident: Ident::new("Self", located_at(span)),
+                    arguments: PathArguments::None,
+                },
+                PathSegment {
+                    ident: gat_ident,
+                    arguments: param_path_arguments(function_params),
+                },
+            ]
+            .into_iter()
+            .collect(),
+        },
+        qself: None,
+    }
+}
+fn handle_item_trait(mut item: ItemTrait, args: &RealAsyncTraitArgs) -> TokenStream {
+    let mut declaration = item.clone();
+    // Hooks given to the trait apply to all of its impls, which learn about them from the
+    // declaration.
+    if let Some(ref hooks) = args.instrument {
+        declaration.attrs.push(syn::parse_quote! { #[real_async_trait(instrument(#hooks))] });
+    }
+
+    // Check that all types have a lifetime that is either specific to the trait item, or to the
+    // current function (or 'static). Any other lifetime will and must produce a compiler error.
+    let validation_errors = item
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Method(method) => Some(method),
+            _ => None,
+        })
+        .filter(|method| method.sig.asyncness.is_some() || returns_impl_trait(&method.sig))
+        .filter_map(|method| validate_signature(&method.sig).err())
+        .collect();
+    if let Err(error) = combine_errors(validation_errors) {
+        return error.to_compile_error();
+    }
+
+    let mut new_gat_items = Vec::new();
+    // The boxed methods, and whether their futures are `Send`.
+    let mut boxed_methods = Vec::new();
+    let mut gats_require_sized_self = false;
+
+    // Loop through every single async fn declared in the trait.
+    for method in item
+        .items
+        .iter_mut()
+        .filter_map(|item| {
+            if let TraitItem::Method(func) = item {
+                Some(func)
+            } else {
+                None
+            }
+        })
+        .filter(|method| method.sig.asyncness.is_some() || returns_impl_trait(&method.sig))
+    {
+        // Methods returning `impl Trait` are lowered in the same way as async fns, except that
+        // the bounds of the generated associated type are the ones written by the user.
+        let is_async = method.sig.asyncness.is_some();
+
+        // For each async fn, remove the async part, replace the return value with a generic
+        // associated type, and add that generic associated type to the trait item.
+
+        let real_async_traits_attributes: HashSet<RealAsyncTraitAttributes> = match parse_attributes(&mut method.attrs) {
+            Ok(attributes) => attributes,
+            Err(error) => return error.to_compile_error(),
+        };
+
+        let gat_ident = gat_ident_for_sig(&method.sig);
+
+        let method_return_ty = return_type(method.sig.output.clone());
+
+        if is_async {
+            method.attrs.extend(async_signature_doc(
+                &method.sig,
+                &method_return_ty,
+                real_async_traits_attributes.contains(&RealAsyncTraitAttributes::Send),
+            ));
+        }
+
+        // Constructors such as `async fn create() -> Self` can only have futures resolving to
+        // `Self` if it is sized.
+        let output_requires_sized_self = mentions_self_type(&method_return_ty);
+        if output_requires_sized_self {
+            method
+                .sig
+                .generics
+                .make_where_clause()
+                .predicates
+                .push(self_sized_predicate());
+        }
+
+        method.sig.asyncness = None;
+
+        desugar_impl_trait_arguments(&mut method.sig);
+        let (toplevel_params, function_params) =
+            already_defined_params(&item.generics, &method.sig.generics);
+
+        // An `impl Trait` within the output of an async method, e.g. `-> Result<impl Read, E>`,
+        // cannot appear in the bound of an associated type, so it gets an associated type of its
+        // own.
+        let mut method_return_ty = method_return_ty;
+        let output_span = output_span(&method.sig);
+        let nested_bounds = if is_async {
+            replace_nested_impl_traits(&mut method_return_ty, |index| {
+                self_gat_type(nested_type_ident(&gat_ident, index), &function_params, output_span)
+                    .into()
+            })
+        } else {
+            Vec::new()
+        };
+
+        if !is_async && method.default.is_some() {
+            return syn::Error::new_spanned(
+                &method.sig.output,
+                format!(
+                    "`{}` returns `impl Trait` and has a default body, which is not supported, since implementors cannot name its return type",
+                    method.sig.ident
+                ),
+            )
+            .to_compile_error();
+        }
+
+        // A provided method cannot name the future of its default body through a GAT, since
+        // implementors are free to pick another type, so its future is boxed instead. Methods can
+        // also opt into boxing explicitly, which is needed for recursive methods, whose futures
+        // would otherwise contain themselves.
+        if is_async
+            && (method.default.is_some()
+                || real_async_traits_attributes.contains(&RealAsyncTraitAttributes::Boxed))
+        {
+            if !nested_bounds.is_empty() {
+                return syn::Error::new_spanned(
+                    &method.sig.output,
+                    format!(
+                        "the output of `{}` contains `impl Trait` and the method is boxed, which is not supported",
+                        method.sig.ident
+                    ),
+                )
+                .to_compile_error();
+            }
+            boxed_methods.push((
+                method.sig.ident.clone(),
+                real_async_traits_attributes.contains(&RealAsyncTraitAttributes::Send),
+            ));
+            let bounds = future_lifetime_bounds(&method.sig, &toplevel_params)
+                .into_iter()
+                .chain(real_async_traits_attributes.into_iter().filter_map(|attr| send_bound(attr, output_span)))
+                .collect();
+
+            method.sig.output = ReturnType::Type(
+                output_arrow(&method.sig),
+                Box::new(boxed_future_type(method_return_ty, bounds)),
+            );
+            if let Some(default_block) = method.default.as_mut() {
+                let mut future = async_move_block(&mut method.sig, default_block);
+                if let Some(ref path) = args.instrument {
+                    let hooks = InstrumentHooks {
+                        path: path.clone(),
+                        trait_name: Some(item.ident.to_string()),
+                    };
+                    future = instrument_future(future, &hooks, &method.sig.ident);
+                }
+                default_block.stmts = vec![Stmt::Expr(boxed_future_expr(future, output_span))];
+            }
+            continue;
+        }
+
+        let gat_generics = {
+            let mut generics = param_generics(&function_params);
+            generics.make_where_clause().predicates.extend(gat_outlives_predicates(
+                &method.sig,
+                &item.generics,
+                &function_params,
+            ));
+            // This includes `Self: Sized`, if the output requires it.
+            generics
+                .make_where_clause()
+                .predicates
+                .extend(where_predicates(&method.sig.generics));
+            remove_empty_where_clause(&mut generics);
+            generics
+        };
+
+        let gat_bounds = iter::once((
+            gat_ident.clone(),
+            returned_type_bounds(&method.sig, is_async, method_return_ty, &toplevel_params)
+                .into_iter()
+                .chain(real_async_traits_attributes.into_iter().filter_map(|attr| send_bound(attr, output_span)))
+                .collect(),
+        ))
+        .chain(
+            nested_bounds
+                .into_iter()
+                .enumerate()
+                .map(|(index, bounds)| (nested_type_ident(&gat_ident, index), bounds)),
+        );
+
+        gats_require_sized_self |= output_requires_sized_self;
+
+        let deprecated_attrs = method
+            .attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("deprecated"))
+            .cloned()
+            .collect::<Vec<_>>();
+        for (ident, bounds) in gat_bounds {
+            new_gat_items.push(TraitItemType {
+                attrs: forwarded_attributes(&method.attrs, false)
+                    .into_iter()
+                    .chain(deprecated_attrs.iter().cloned())
+                    .chain(iter::once(syn::parse_quote! { #[doc(hidden)] }))
+                    .collect(),
+                type_token: Token!(type)(Span::call_site()),
+                bounds,
+                colon_token: Some(Token!(:)(Span::call_site())),
+                default: None,
+                generics: gat_generics.clone(),
+                ident,
+                semi_token: Token!(;)(Span::call_site()),
+            });
+        }
+
+        let self_gat_type = self_gat_type(gat_ident, &function_params, output_span);
+
+        method.sig.output = ReturnType::Type(
+            output_arrow(&method.sig),
+            Box::new(self_gat_type.into()),
+        );
+        // The signature now names the deprecated associated type. Methods with an associated type
+        // never have a default body, so allowing the lint does not affect any user code.
+        if !deprecated_attrs.is_empty() {
+            method.attrs.push(syn::parse_quote! { #[allow(deprecated)] });
+        }
+    }
+    let variant_traits = args
+        .variants
+        .iter()
+        .map(|variant| variant_trait(&item, variant, &new_gat_items, &boxed_methods, gats_require_sized_self))
+        .collect::<Vec<_>>();
+
+    item.items
+        .extend(new_gat_items.into_iter().map(TraitItem::Type));
+
+    let metadata_macro = metadata_macro(&declaration);
+    let interceptor = args
+        .interceptor
+        .as_ref()
+        .map(|struct_ident| interceptor(&declaration, struct_ident));
+
+    quote! {
+        #item
+
+        #(#variant_traits)*
+
+        #metadata_macro
+
+        #interceptor
+    }
+}
+
+/// Generates a struct that implements the trait by delegating to an inner implementation, and that
+/// calls the `before` and `after` methods of a hooks trait, also generated, around every method
+/// that has a receiver. The impl is expanded like any other, so its futures are not boxed.
+fn interceptor(declaration: &ItemTrait, struct_ident: &Ident) -> TokenStream {
+    let vis = &declaration.vis;
+    let trait_ident = &declaration.ident;
+    let hooks_ident = quote::format_ident!("{}Hooks", struct_ident);
+    let debug_module = quote::format_ident!("__real_async_trait_impl_DebugFor_{}", struct_ident);
+    let module_ident = quote::format_ident!(
+        "__real_async_trait_impl_{}",
+        struct_ident.to_string().to_lowercase()
+    );
+
+    let (_, trait_ty_generics, _) = declaration.generics.split_for_impl();
+    let trait_path: Path = syn::parse_quote! { #trait_ident #trait_ty_generics };
+    let inner_ty: Type = syn::parse_quote! { __RealAsyncTraitImplInner };
+
+    let mut errors = Vec::new();
+    let items = declaration
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Method(method) => {
+                match intercepted_method(method, &inner_ty, &trait_path, &debug_module) {
+                    Ok(method) => Some(ImplItem::Method(method)),
+                    Err(error) => {
+                        errors.push(error);
+                        None
+                    }
+                }
+            }
+            TraitItem::Type(item_type) => {
+                let ident = &item_type.ident;
+                let generics = &item_type.generics;
+                let (_, ty_generics, where_clause) = generics.split_for_impl();
+                Some(syn::parse_quote! {
+                    type #ident #generics = <#inner_ty as #trait_path>::#ident #ty_generics #where_clause;
+                })
+            }
+            TraitItem::Const(item_const) => {
+                let ident = &item_const.ident;
+                let ty = &item_const.ty;
+                Some(syn::parse_quote! {
+                    const #ident: #ty = <#inner_ty as #trait_path>::#ident;
+                })
+            }
+            item => {
+                errors.push(syn::Error::new_spanned(
+                    item,
+                    "`interceptor` only supports traits made of methods, associated types and associated consts",
+                ));
+                None
+            }
+        })
+        .collect::<Vec<ImplItem>>();
+    if let Err(error) = combine_errors(errors) {
+        return error.to_compile_error();
+    }
+
+    let mut generics = declaration.generics.clone();
+    for param in generics.params.iter_mut() {
+        if let GenericParam::Type(param) = param {
+            param.eq_token = None;
+            param.default = None;
+        }
+    }
+    generics.params.push(syn::parse_quote! { #inner_ty: #trait_path });
+    generics.params.push(syn::parse_quote! { __RealAsyncTraitImplHooks: #hooks_ident });
 
-    TraitBound {
-        // for TraitBounds, these are HRTBs, which are useless since there are already GATs present
-        lifetimes: None,
-        // This is not ?Sized or something like that
-        modifier: TraitBoundModifier::None,
-        paren_token: None,
-        path: future_trait_path,
+    // The futures of `Send` methods hold on to the wrapper through their receiver, which is only
+    // `Send` if both halves of the wrapper are `Sync`, or `Send` for `&mut self` and `self`.
+    let mut send_bounds = HashSet::new();
+    for method in declaration.items.iter().filter_map(|item| match item {
+        TraitItem::Method(method) if method.sig.asyncness.is_some() => Some(method),
+        _ => None,
+    }) {
+        if !parse_attributes(&mut method.attrs.clone())
+            .unwrap_or_default()
+            .contains(&RealAsyncTraitAttributes::Send)
+        {
+            continue;
+        }
+        match method.sig.receiver() {
+            Some(FnArg::Receiver(Receiver {
+                reference: Some(_),
+                mutability: None,
+                ..
+            })) => send_bounds.insert("Sync"),
+            Some(_) => send_bounds.insert("Send"),
+            None => false,
+        };
     }
-}
+    for bound in ["Send", "Sync"].iter().filter(|bound| send_bounds.contains(*bound)) {
+        let bound = Ident::new(bound, Span::call_site());
+        let predicates = &mut generics.make_where_clause().predicates;
+        predicates.push(syn::parse_quote! { #inner_ty: ::core::marker::#bound });
+        predicates.push(syn::parse_quote! { __RealAsyncTraitImplHooks: ::core::marker::#bound });
+    }
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    let item_impl: ItemImpl = syn::parse_quote! {
+        impl #impl_generics #trait_path for #struct_ident<#inner_ty, __RealAsyncTraitImplHooks> #where_clause {
+            #(#items)*
+        }
+    };
+    let item_impl = handle_item_impl(
+        item_impl,
+        &RealAsyncTraitArgs::default(),
+        Some(declaration),
+        &module_ident,
+    );
+
+    let struct_doc = format!(
+        "Implements `{}` by delegating to `inner`, and by telling `hooks` about every call of a \
+         method with a receiver, before and after it.",
+        trait_ident
+    );
+    let hooks_doc = format!(
+        "The hooks of [`{}`], which are given the arguments of a method before it is called, and \
+         its output once it returns. Arguments and outputs that do not implement `Debug` are \
+         shown as `_`.",
+        struct_ident
+    );
+
+    quote! {
+        #[doc = #struct_doc]
+        #vis struct #struct_ident<T, H> {
+            pub inner: T,
+            pub hooks: H,
+        }
+
+        impl<T, H> #struct_ident<T, H> {
+            pub fn new(inner: T, hooks: H) -> Self {
+                Self { inner, hooks }
+            }
+        }
+
+        #[doc = #hooks_doc]
+        #vis trait #hooks_ident {
+            fn before(&self, method: &'static str, args: &[&dyn ::core::fmt::Debug]) {
+                let _ = (method, args);
+            }
+            fn after(&self, method: &'static str, output: &dyn ::core::fmt::Debug) {
+                let _ = (method, output);
+            }
+        }
+
+        // Arguments are shown through whichever of the traits applies first, with a `Debug` impl
+        // taking precedence over the fallback through one more level of references.
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        mod #debug_module {
+            pub struct DebugArg<'a, A>(pub &'a A);
 
-impl From<RealAsyncTraitAttributes> for TypeParamBound{
-    fn from(attr: RealAsyncTraitAttributes)->TypeParamBound{
-        const SEND_TRAIT_PATH_STR: &str = "::core::marker::Send";
-        match attr{
-            RealAsyncTraitAttributes::Send=>{
-                let path = syn::parse2::<Path>(TokenStream::from_str(SEND_TRAIT_PATH_STR).unwrap())
-                    .expect(&format!("{}{}{}", "Failed to parse ", SEND_TRAIT_PATH_STR, "into path"));
-                TypeParamBound::Trait(
-                    TraitBound{
-                    lifetimes: None,
-                    modifier: TraitBoundModifier::None,
-                    paren_token: None,
-                    path: path,
+            pub struct Opaque;
+
+            impl ::core::fmt::Debug for Opaque {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    f.write_str("_")
+                }
+            }
+
+            pub trait ViaDebug {
+                fn debug_arg(&self) -> &dyn ::core::fmt::Debug;
+            }
+
+            impl<A: ::core::fmt::Debug> ViaDebug for &DebugArg<'_, A> {
+                fn debug_arg(&self) -> &dyn ::core::fmt::Debug {
+                    self.0
+                }
+            }
+
+            pub trait ViaOpaque {
+                fn debug_arg(&self) -> &dyn ::core::fmt::Debug;
+            }
+
+            impl<A> ViaOpaque for DebugArg<'_, A> {
+                fn debug_arg(&self) -> &dyn ::core::fmt::Debug {
+                    &Opaque
                 }
-            )
             }
-            #[allow(unreachable_patterns)]
-            other_variants => panic!("Attempted to convert RealAsyncTraitAttributes into a path, no conversion for the variant found. Variant was: {:?}", other_variants),
         }
+
+        #item_impl
     }
 }
 
+/// The method of an interceptor impl, which forwards to the inner implementation, and, given a
+/// receiver, tells the hooks about the call.
+fn intercepted_method(
+    method: &TraitItemMethod,
+    inner_ty: &Type,
+    trait_path: &Path,
+    debug_module: &Ident,
+) -> Result<ImplItemMethod, syn::Error> {
+    let mut sig = method.sig.clone();
+    let method_ident = &sig.ident;
 
-fn validate_that_function_always_has_lifetimes(signature: &Signature) {
-    for input in signature.inputs.iter() {
+    let mut receiver = None;
+    let mut call_args = Vec::new();
+    let mut debug_args = Vec::new();
+    for (index, input) in sig.inputs.iter_mut().enumerate() {
         match input {
-            FnArg::Receiver(ref recv) => {
-                if let Some((_ampersand, _lifetime @ None)) = &recv.reference {
-                    panic!("{}self parameter lacked an explicit lifetime, which is required by this proc macro", if recv.mutability.is_some() { "&mut " } else { "&" });
-                }
+            FnArg::Receiver(Receiver {
+                reference,
+                mutability,
+                self_token,
+                ..
+            }) => {
+                call_args.push(match (reference, mutability) {
+                    (Some(_), Some(_)) => quote! { &mut #self_token.inner },
+                    (Some(_), None) => quote! { &#self_token.inner },
+                    (None, _) => quote! { #self_token.inner },
+                });
+                receiver = Some(*self_token);
             }
-            FnArg::Typed(PatType { ref ty, .. }) => {
-                syn::visit::visit_type(&mut LifetimeVisitor, ty)
+            FnArg::Typed(PatType { pat, ty, .. }) => {
+                if let Pat::Ident(PatIdent { ident, .. }) = &**pat {
+                    if ident == "self" {
+                        return Err(syn::Error::new_spanned(
+                            &**ty,
+                            format!(
+                                "`interceptor` cannot delegate `{}`, whose receiver is not `self`, `&self` or `&mut self`",
+                                method_ident
+                            ),
+                        ));
+                    }
+                }
+                if mentions_self_type(ty) {
+                    return Err(syn::Error::new_spanned(
+                        &**ty,
+                        format!(
+                            "`interceptor` cannot delegate `{}`, which takes `Self` as an argument",
+                            method_ident
+                        ),
+                    ));
+                }
+                let arg_ident = match **pat {
+                    Pat::Ident(PatIdent {
+                        by_ref: None,
+                        mutability: None,
+                        ref ident,
+                        subpat: None,
+                        ..
+                    }) => ident.clone(),
+                    _ => quote::format_ident!("__real_async_trait_impl_arg{}", index),
+                };
+                **pat = syn::parse_quote! { #arg_ident };
+                call_args.push(quote! { #arg_ident });
+                debug_args.push(quote! {
+                    (&&super::#debug_module::DebugArg(&#arg_ident)).debug_arg()
+                });
             }
         }
     }
-    if let ReturnType::Type(_, ref ty) = signature.output {
-        syn::visit::visit_type(&mut LifetimeVisitor, ty);
+    if let ReturnType::Type(_, ref output) = sig.output {
+        if mentions_self_type(output) {
+            return Err(syn::Error::new_spanned(
+                output,
+                format!(
+                    "`interceptor` cannot delegate `{}`, which returns `Self`",
+                    method_ident
+                ),
+            ));
+        }
+    }
+
+    let explicit_params = sig
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(&param.ident),
+            GenericParam::Const(param) => Some(&param.ident),
+            GenericParam::Lifetime(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let turbofish = if explicit_params.is_empty() {
+        None
+    } else {
+        Some(quote! { ::<#(#explicit_params),*> })
     };
-}
-fn already_defined_lifetimes(
-    toplevel_generics: &Generics,
-    method_generics: &Generics,
-) -> (Vec<LifetimeDef>, Vec<LifetimeDef>) {
-    //Global scope
-    //let mut lifetimes = vec! [LifetimeDef::new(Lifetime::new("'static", Span::call_site()))];
+    let mut call = quote! { <#inner_ty as #trait_path>::#method_ident #turbofish(#(#call_args),*) };
+    if sig.unsafety.is_some() {
+        call = quote! { unsafe { #call } };
+    }
+    if sig.asyncness.is_some() {
+        call = quote! { #call.await };
+    }
 
-    let mut lifetimes = Vec::new();
-    // Trait definition scope
-    lifetimes.extend(toplevel_generics.lifetimes().cloned());
-    // Function definition scope
-    let function_lifetimes = method_generics.lifetimes().cloned().collect::<Vec<_>>();
-    lifetimes.extend(function_lifetimes.iter().cloned());
-    (lifetimes, function_lifetimes)
-}
-fn lifetime_angle_bracketed_bounds(
-    lifetimes: impl IntoIterator<Item = Lifetime>,
-) -> AngleBracketedGenericArguments {
-    AngleBracketedGenericArguments {
-        colon2_token: None,
-        lt_token: Token!(<)(Span::call_site()),
-        gt_token: Token!(>)(Span::call_site()),
-        args: lifetimes
-            .into_iter()
-            .map(|lifetime_def| GenericArgument::Lifetime(lifetime_def))
-            .collect(),
+    let name = method_ident.to_string();
+    let block: Block = match receiver {
+        Some(self_token) => syn::parse_quote! {{
+            use super::#debug_module::{ViaDebug as _, ViaOpaque as _};
+            #self_token.hooks.before(#name, &[#(#debug_args),*]);
+            let __real_async_trait_impl_output = #call;
+            #self_token.hooks.after(
+                #name,
+                (&&super::#debug_module::DebugArg(&__real_async_trait_impl_output)).debug_arg(),
+            );
+            __real_async_trait_impl_output
+        }},
+        None => syn::parse_quote! {{ #call }},
+    };
+
+    // Delegating to a deprecated method is not a use of it that the user could avoid.
+    let mut attrs = forwarded_attributes(&method.attrs, false);
+    if is_deprecated(&method.attrs) {
+        attrs.push(syn::parse_quote! { #[allow(deprecated)] });
     }
+
+    Ok(ImplItemMethod {
+        attrs,
+        vis: Visibility::Inherited,
+        defaultness: None,
+        sig,
+        block,
+    })
 }
-fn gat_ident_for_sig(sig: &Signature) -> Ident {
-    let gat_name = format!("__real_async_trait_impl_TypeFor_{}", sig.ident);
-    Ident::new(&gat_name, Span::call_site())
-}
-fn self_gat_type(
-    gat_ident: Ident,
-    function_lifetimes: impl IntoIterator<Item = Lifetime>,
-) -> TypePath {
-    TypePath {
-        path: Path {
-            // represents the pattern Self::GAT_NAME...
-            leading_colon: None,
-            segments: vec![
-                PathSegment {
-                    ident: Ident::new("Self", Span::call_site()),
-                    arguments: PathArguments::None,
-                },
-                PathSegment {
-                    ident: gat_ident,
-                    arguments: PathArguments::AngleBracketed(lifetime_angle_bracketed_bounds(
-                        function_lifetimes,
-                    )),
-                },
-            ]
-            .into_iter()
-            .collect(),
-        },
-        qself: None,
+
+/// The number of metadata macros generated so far, which keeps their names unique within the crate
+/// being compiled.
+///
+/// A counter is safe, since the unique name is only ever referred to by the re-export emitted
+/// along with the macro, so names only have to be unique, not stable across compilations. The
+/// proc-macro library may stay loaded for several crates, e.g. by rust-analyzer, which only makes
+/// the counter start higher. Spans would not do instead, since identical traits expanded from the
+/// same `macro_rules!` share them.
+static METADATA_MACRO_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A hidden macro carrying the declaration of a trait, which is what lets impls learn about the
+/// trait, even from other crates. It is exported under a unique name, and re-exported next to the
+/// trait under the name of the trait, so that any path to the trait is a path to the macro as
+/// well.
+///
+/// The impl expansion invokes it with a call to the second stage of the impl expansion, i.e.
+/// `Trait! { ::real_async_trait::__real_async_trait_impl! { (args) impl ... } }`, to which it
+/// prepends the declaration of the trait.
+fn metadata_macro(declaration: &ItemTrait) -> TokenStream {
+    // Paths starting with `crate`, such as those of hooks, have to refer to the crate of the trait
+    // rather than to that of the impl.
+    let declaration_tokens = dollar_crate(declaration.to_token_stream());
+
+    // FNV-1a, which unlike the hasher of the standard library is guaranteed to stay the same.
+    let hash = declaration_tokens
+        .to_string()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    // Identical declarations in different modules of a crate would still collide, as exported
+    // macros share the root of the crate, so each expansion within the compilation is numbered.
+    let index = METADATA_MACRO_COUNT.fetch_add(1, Ordering::Relaxed);
+    let trait_ident = &declaration.ident;
+    let macro_ident = Ident::new(
+        &format!("__real_async_trait_metadata_{}_{:016x}_{}", trait_ident, hash, index),
+        Span::call_site(),
+    );
+    let vis = &declaration.vis;
+
+    quote! {
+        #[doc(hidden)]
+        #[macro_export]
+        macro_rules! #macro_ident {
+            (:: $($path:ident)::+ ! { $($input:tt)* }) => {
+                :: $($path)::+ ! { { #declaration_tokens } $($input)* }
+            };
+        }
+        #[doc(hidden)]
+        #vis use #macro_ident as #trait_ident;
     }
 }
-fn handle_item_trait(mut item: ItemTrait) -> TokenStream {
-
-    let mut new_gat_items = Vec::new();
 
-    // Loop through every single async fn declared in the trait.
-    for method in item
-        .items
-        .iter_mut()
-        .filter_map(|item| {
-            if let TraitItem::Method(func) = item {
-                Some(func)
-            } else {
-                None
+/// Replaces `crate` with `$crate` within a token stream that becomes the body of a `macro_rules!`.
+fn dollar_crate(tokens: TokenStream) -> TokenStream {
+    tokens
+        .into_iter()
+        .flat_map(|tree| match tree {
+            TokenTree::Ident(ref ident) if ident == "crate" => {
+                vec![TokenTree::Punct(proc_macro2::Punct::new('$', proc_macro2::Spacing::Alone)), tree]
             }
+            TokenTree::Group(ref group) => {
+                let mut replaced = Group::new(group.delimiter(), dollar_crate(group.stream()));
+                replaced.set_span(group.span());
+                vec![replaced.into()]
+            }
+            tree => vec![tree],
         })
-        .filter(|method| method.sig.asyncness.is_some())
-    {
-        
-        // For each async fn, remove the async part, replace the return value with a generic
-        // associated type, and add that generic associated type to the trait item.
-
-        // Check that all types have a lifetime that is either specific to the trait item, or
-        // to the current function (or 'static). Any other lifetime will and must produce a
-        // compiler error.
-        let real_async_traits_attributes: HashSet<RealAsyncTraitAttributes> =  parse_attributes(&mut method.attrs);
+        .collect()
+}
 
-        let gat_ident = gat_ident_for_sig(&method.sig);
+/// A subtrait whose generated associated types are all bounded by the bounds of the variant, e.g.
+/// `Send`, along with a blanket impl for every implementor of the trait for which they hold.
+fn variant_trait(
+    item: &ItemTrait,
+    variant: &TraitVariant,
+    gats: &[TraitItemType],
+    boxed_methods: &[(Ident, bool)],
+    gats_require_sized_self: bool,
+) -> TokenStream {
+    let TraitVariant { ident, bounds } = variant;
+    let trait_ident = &item.ident;
+    let vis = &item.vis;
 
-        let method_return_ty = return_type(method.sig.output.clone());
+    // The futures of boxed methods have no associated type to bound, so their bounds are fixed by
+    // the trait. A `Send` variant is only correct if they are all `Send` already.
+    let only_send = bounds.len() == 1 && bounds.iter().all(is_send_bound);
+    if let Some((method_ident, _)) = boxed_methods.iter().find(|(_, is_send)| !(only_send && *is_send)) {
+        let message = if only_send {
+            format!(
+                "`variant` cannot bound the future of `{}`, which is boxed, unless it is marked `#[real_async_trait(Send)]`",
+                method_ident
+            )
+        } else {
+            format!(
+                "`variant` cannot bound the future of `{}`, which is boxed, by anything but `Send`",
+                method_ident
+            )
+        };
+        return syn::Error::new(ident.span(), message).to_compile_error();
+    }
 
-        validate_that_function_always_has_lifetimes(&method.sig);
+    // The bounds of the subtrait quantify over the lifetimes of the generated associated types,
+    // which is not possible for types.
+    if let Some(gat) = gats.iter().find(|gat| gat.generics.type_params().next().is_some()) {
+        return syn::Error::new(
+            ident.span(),
+            format!(
+                "`variant` is not supported for traits with generic async methods, such as the one behind `{}`",
+                gat.ident
+            ),
+        )
+        .to_compile_error();
+    }
 
-        method.sig.asyncness = None;
+    let mut lifetimes = Vec::new();
+    for lifetime_def in gats.iter().flat_map(|gat| gat.generics.lifetimes()) {
+        if !lifetimes.contains(&lifetime_def.lifetime) {
+            lifetimes.push(lifetime_def.lifetime.clone());
+        }
+    }
+    let higher_ranked_lifetimes = if lifetimes.is_empty() {
+        None
+    } else {
+        Some(quote! { for<#(#lifetimes),*> })
+    };
 
-        let (toplevel_lifetimes, function_lifetimes) =
-            already_defined_lifetimes(&item.generics, &method.sig.generics);
+    let trait_args = item
+        .generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Type(type_param) => type_param.ident.to_token_stream(),
+            GenericParam::Lifetime(lifetime_def) => lifetime_def.lifetime.to_token_stream(),
+            GenericParam::Const(const_param) => const_param.ident.to_token_stream(),
+        })
+        .collect::<Vec<_>>();
+    let gat_bounds = gats.iter().map(|gat| {
+        let gat_ident = &gat.ident;
+        let gat_args =
+            lifetime_path_arguments(gat.generics.lifetimes().map(|def| def.lifetime.clone()));
+        quote! { #gat_ident #gat_args: #bounds }
+    });
+    let bounded_trait = quote! {
+        #higher_ranked_lifetimes #trait_ident<#(#trait_args,)* #(#gat_bounds),*>
+    };
 
+    // Generated associated types that resolve to `Self` require it to be sized.
+    let sized_bound = if gats_require_sized_self {
+        Some(quote! { ::core::marker::Sized + })
+    } else {
+        None
+    };
+    let implementor_bounds = if gats_require_sized_self {
+        None
+    } else {
+        Some(quote! { : ?::core::marker::Sized })
+    };
 
-//println!("[{}:{}] {:?}", file!(), line!(),test_bounds);
-        new_gat_items.push(TraitItemType {
-            attrs: Vec::new(),
-            type_token: Token!(type)(Span::call_site()),
-            bounds: iter::once(TypeParamBound::Trait(future_trait_bound(method_return_ty)))
-                .chain(
-                    toplevel_lifetimes
-                        .into_iter()
-                        .map(|lifetime_def| lifetime_def.lifetime)
-                        .map(TypeParamBound::Lifetime),
-                ).chain(
-                    real_async_traits_attributes
-                    .into_iter()
-                    .map(|attr| TypeParamBound::from(attr))
-                )
-                .collect(),
-            colon_token: Some(Token!(:)(Span::call_site())),
-            default: None,
-            generics: Generics {
-                lt_token: Some(Token!(<)(Span::call_site())),
-                gt_token: Some(Token!(>)(Span::call_site())),
-                where_clause: None,
-                params: function_lifetimes
-                    .iter()
-                    .cloned()
-                    .map(GenericParam::Lifetime)
-                    .collect(),
-            },
-            ident: gat_ident.clone(),
-            semi_token: Token!(;)(Span::call_site()),
-        });
+    // Defaults of generic parameters are not allowed on impls.
+    let impl_params = item.generics.params.iter().cloned().map(|mut param| {
+        match param {
+            GenericParam::Type(ref mut type_param) => {
+                type_param.eq_token = None;
+                type_param.default = None;
+            }
+            GenericParam::Const(ref mut const_param) => {
+                const_param.eq_token = None;
+                const_param.default = None;
+            }
+            GenericParam::Lifetime(_) => (),
+        }
+        param
+    });
+    let trait_params = &item.generics.params;
+    let where_clause = &item.generics.where_clause;
+    let where_predicates = where_clause.iter().flat_map(|where_clause| &where_clause.predicates);
 
-        let self_gat_type = self_gat_type(
-            gat_ident,
-            function_lifetimes
-                .into_iter()
-                .map(|lifetime_def| lifetime_def.lifetime),
-        );
+    // The bounds name the associated types of deprecated methods as well.
+    let allow_deprecated = if gats.iter().any(|gat| is_deprecated(&gat.attrs)) {
+        Some(quote! { #[allow(deprecated)] })
+    } else {
+        None
+    };
 
-        method.sig.output = ReturnType::Type(
-            Token!(->)(Span::call_site()),
-            Box::new(self_gat_type.into()),
-        );
+    let mut doc = format!(
+        "A variant of [`{}`] whose futures are all bounded by `{}`.\n\nIt is implemented for every implementor of [`{}`] for which these bounds hold.",
+        trait_ident,
+        tokens_to_string(bounds.to_token_stream()),
+        trait_ident,
+    );
+    // The bounds quantify over lifetimes that `Self` has to outlive, which the compiler only
+    // manages to prove for `Self: 'static`.
+    if higher_ranked_lifetimes.is_some() {
+        doc.push_str(" Since the bounds have to hold for all lifetimes, only `'static` implementors implement it.");
     }
-    item.items
-        .extend(new_gat_items.into_iter().map(TraitItem::Type));
 
     quote! {
-        #item
+        #[doc = #doc]
+        #allow_deprecated
+        #vis trait #ident<#trait_params>: #sized_bound #bounded_trait #where_clause {}
+
+        #allow_deprecated
+        impl<#(#impl_params,)* __RealAsyncTraitImplementor #implementor_bounds> #ident<#(#trait_args),*>
+            for __RealAsyncTraitImplementor
+        where
+            __RealAsyncTraitImplementor: #sized_bound #bounded_trait,
+            #(#where_predicates,)*
+        {}
     }
 }
 
@@ -578,6 +3428,7 @@ fn handle_item_trait(mut item: ItemTrait) -> TokenStream {
 #[derive(Hash, PartialEq, Eq, Debug, Clone)]
 enum RealAsyncTraitAttributes{
     Send,
+    Boxed,
 }
 
 
@@ -587,24 +3438,384 @@ impl std::str::FromStr for RealAsyncTraitAttributes{
         let trimmed_str = s.trim();
         match trimmed_str{
             "Send"|"send" => Ok(RealAsyncTraitAttributes::Send),
+            "boxed" => Ok(RealAsyncTraitAttributes::Boxed),
             _ => Err(format!("Could not parse {} into an attribute", s)),
         }
     }
 }
 
-fn real_async_trait2(_args_stream: TokenStream, token_stream: TokenStream) -> TokenStream {
-    // The #[real_async_trait] attribute macro, is applicable to both trait blocks, and to impl
-    // blocks that operate on that trait.
+/// The arguments of the `#[real_async_trait(...)]` attribute on a trait or impl block itself, as
+/// opposed to the per-method attributes.
+#[derive(Default)]
+struct RealAsyncTraitArgs {
+    /// The largest size in bytes that the future of any async method in an impl may have.
+    max_future_size: Option<usize>,
+    /// Subtraits to generate alongside a trait, given as `variant(SendRedoxScheme: Send)`.
+    variants: Vec<TraitVariant>,
+    /// Attribute macros besides [`METHOD_ATTRIBUTE_MACROS`] that have to see the async methods of
+    /// an impl as `async fn`s, given as `method_attributes(timed, metrics::measure)`.
+    method_attributes: Vec<Path>,
+    /// Hooks that are told about every poll of the futures of an impl, given as
+    /// `instrument(SchemeHooks)`.
+    instrument: Option<Path>,
+    /// Whether the futures of an impl record how they are polled, given as `metrics`.
+    metrics: bool,
+    /// The name of a wrapper struct to generate alongside a trait, which implements it by
+    /// delegating to another implementation and calling hooks around its async methods, given as
+    /// `interceptor` or `interceptor(LoggedScheme)`.
+    interceptor: Option<Ident>,
+    /// The names of the arguments as given, which errors about misplaced arguments point at.
+    given: Vec<Ident>,
+}
+
+impl RealAsyncTraitArgs {
+    /// An error at the argument `name`, if it was given to an item that it does not apply to.
+    fn misplaced(&self, name: &str, message: &str) -> Option<syn::Error> {
+        self.given
+            .iter()
+            .find(|ident| *ident == name)
+            .map(|ident| syn::Error::new(ident.span(), message))
+    }
+
+    fn validate_for_trait(&self) -> Result<(), syn::Error> {
+        combine_errors(
+            vec![
+                self.misplaced(
+                    "max_future_size",
+                    "`max_future_size` can only be given to impl blocks, which define the futures",
+                ),
+                self.misplaced(
+                    "method_attributes",
+                    "`method_attributes` can only be given to impl blocks, whose methods they apply to",
+                ),
+                self.misplaced(
+                    "metrics",
+                    "`metrics` can only be given to impl blocks, which define the futures",
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        )
+    }
+
+    fn validate_for_impl(&self) -> Result<(), syn::Error> {
+        combine_errors(
+            vec![
+                self.misplaced(
+                    "variant",
+                    "`variant` can only be given to traits, since it generates a subtrait",
+                ),
+                self.misplaced(
+                    "interceptor",
+                    "`interceptor` can only be given to traits, since it generates an impl of them",
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        )
+    }
+
+    fn validate_for_fn(&self) -> Result<(), syn::Error> {
+        combine_errors(
+            vec![
+                self.misplaced(
+                    "variant",
+                    "`variant` can only be given to traits, since it generates a subtrait",
+                ),
+                self.misplaced(
+                    "instrument",
+                    "`instrument` can only be given to traits and impl blocks",
+                ),
+                self.misplaced("metrics", "`metrics` can only be given to impl blocks"),
+                self.misplaced(
+                    "interceptor",
+                    "`interceptor` can only be given to traits, since it generates an impl of them",
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        )
+    }
+
+    fn validate_for_mod(&self) -> Result<(), syn::Error> {
+        combine_errors(
+            vec![
+                self.misplaced(
+                    "variant",
+                    "`variant` can only be given to traits, since it generates a subtrait",
+                ),
+                self.misplaced(
+                    "interceptor",
+                    "`interceptor` can only be given to traits, including those within the module",
+                ),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        )
+    }
+}
+
+struct TraitVariant {
+    ident: Ident,
+    bounds: Punctuated<TypeParamBound, Token![+]>,
+}
+
+impl Parse for TraitVariant {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let bounds = Punctuated::parse_separated_nonempty(input)?;
+        Ok(Self { ident, bounds })
+    }
+}
+
+impl Parse for RealAsyncTraitArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Self::default();
+
+        while !input.is_empty() {
+            let ident = input.parse::<Ident>()?;
+
+            if ident == "max_future_size" {
+                input.parse::<Token![=]>()?;
+                args.max_future_size = Some(input.parse::<LitInt>()?.base10_parse()?);
+            } else if ident == "variant" {
+                let content;
+                syn::parenthesized!(content in input);
+                args.variants.push(content.parse()?);
+            } else if ident == "metrics" {
+                args.metrics = true;
+            } else if ident == "interceptor" {
+                args.interceptor = Some(if input.peek(token::Paren) {
+                    let content;
+                    syn::parenthesized!(content in input);
+                    content.parse()?
+                } else {
+                    Ident::new("Intercepted", ident.span())
+                });
+            } else if ident == "instrument" {
+                let content;
+                syn::parenthesized!(content in input);
+                args.instrument = Some(content.call(Path::parse_mod_style)?);
+            } else if ident == "method_attributes" {
+                let content;
+                syn::parenthesized!(content in input);
+                args.method_attributes.extend(Punctuated::<Path, Token![,]>::parse_terminated_with(
+                    &content,
+                    Path::parse_mod_style,
+                )?);
+            } else {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!("unknown argument `{}`", ident),
+                ));
+            }
+            args.given.push(ident);
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(args)
+    }
+}
 
+fn real_async_trait2(args_stream: TokenStream, token_stream: TokenStream) -> TokenStream {
+    // The #[real_async_trait] attribute macro, is applicable to both trait blocks, and to impl
+    // blocks that operate on that trait, as well as to modules containing them.
+    let args = match syn::parse2::<RealAsyncTraitArgs>(args_stream.clone()) {
+        Ok(args) => args,
+        Err(error) => return error.to_compile_error(),
+    };
 
     if let Ok(item_trait) = syn::parse2::<ItemTrait>(token_stream.clone()) {
-        handle_item_trait(item_trait)
-    } else if let Ok(item_impl) = syn::parse2::<ItemImpl>(token_stream) {
-        handle_item_impl(item_impl)
+        if let Err(error) = args.validate_for_trait() {
+            return error.to_compile_error();
+        }
+        handle_item_trait(item_trait, &args)
+    } else if let Ok(item_impl) = syn::parse2::<ItemImpl>(token_stream.clone()) {
+        if let Err(error) = args.validate_for_impl() {
+            return error.to_compile_error();
+        }
+        match item_impl.trait_ {
+            // Only the macro generated alongside the trait knows its declaration, so the impl is
+            // handed over to it, and from there to the second stage of the expansion.
+            Some((_, ref trait_path, _)) => {
+                let mut macro_path = trait_path.clone();
+                if let Some(last_segment) = macro_path.segments.last_mut() {
+                    last_segment.arguments = PathArguments::None;
+                }
+                quote! {
+                    #macro_path! {
+                        ::real_async_trait::__real_async_trait_impl! { (#args_stream) #item_impl }
+                    }
+                }
+            }
+            None => handle_item_impl(item_impl, &args, None, &impl_module_ident(None)),
+        }
+    } else if let Ok(item_fn) = syn::parse2::<ItemFn>(token_stream.clone()) {
+        if let Err(error) = args.validate_for_fn() {
+            return error.to_compile_error();
+        }
+        handle_item_fn(item_fn, &args)
+    } else if let Ok(item_mod) = syn::parse2::<ItemMod>(token_stream.clone()) {
+        if let Err(error) = args.validate_for_mod() {
+            return error.to_compile_error();
+        }
+        handle_item_mod(item_mod, &args)
+    } else {
+        syn::Error::new_spanned(token_stream, "expected a trait, an impl, an async fn or a module item")
+            .to_compile_error()
+    }
+}
+
+/// The input of the second stage of the impl expansion, i.e. `{ trait declaration } (arguments)
+/// impl block`.
+struct ImplInput {
+    trait_declaration: ItemTrait,
+    args: RealAsyncTraitArgs,
+    item: ItemImpl,
+}
+
+impl Parse for ImplInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let trait_declaration;
+        syn::braced!(trait_declaration in input);
+        let args;
+        syn::parenthesized!(args in input);
+
+        Ok(Self {
+            trait_declaration: trait_declaration.parse()?,
+            args: args.parse()?,
+            item: input.parse()?,
+        })
+    }
+}
+
+fn real_async_trait_impl2(token_stream: TokenStream) -> TokenStream {
+    let ImplInput {
+        trait_declaration,
+        args,
+        item,
+    } = match syn::parse2(token_stream) {
+        Ok(input) => input,
+        Err(error) => return error.to_compile_error(),
+    };
+
+    handle_item_impl(item, &args, Some(&trait_declaration), &impl_module_ident(None))
+}
+
+/// The name of the module that holds an expanded impl. Impls expanded together in a module are
+/// numbered, so that their modules do not collide.
+fn impl_module_ident(index: Option<usize>) -> Ident {
+    match index {
+        Some(index) => quote::format_ident!("__real_async_trait_impl_{}", index),
+        None => Ident::new("__real_async_trait_impl", Span::call_site()),
+    }
+}
+
+/// Removes a `#[real_async_trait(...)]` attribute from an item within a module, returning its
+/// arguments.
+fn take_item_args(attrs: &mut Vec<Attribute>) -> syn::Result<Option<RealAsyncTraitArgs>> {
+    let index = match attrs.iter().position(|attr| {
+        attr.path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "real_async_trait")
+    }) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let attr = attrs.remove(index);
+
+    if attr.tokens.is_empty() {
+        Ok(Some(RealAsyncTraitArgs::default()))
     } else {
-        panic!("expected either a trait or an impl item")
+        attr.parse_args().map(Some)
+    }
+}
+
+fn handle_item_mod(mut item: ItemMod, args: &RealAsyncTraitArgs) -> TokenStream {
+    let items = match item.content {
+        Some((_, ref mut items)) => mem::take(items),
+        None => {
+            return syn::Error::new(
+                item.ident.span(),
+                "#[real_async_trait] can only be applied to modules with inline content",
+            )
+            .to_compile_error();
+        }
+    };
+
+    // The impls of traits declared in the module are expanded directly, with the declaration as
+    // written, rather than through the metadata macro.
+    let declarations = items
+        .iter()
+        .filter_map(|item| match item {
+            // The attribute stays on the declaration, which is where impls look for the hooks of the
+            // trait.
+            Item::Trait(item_trait) => Some(item_trait.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut impl_count = 0;
+
+    let expanded_items = items
+        .into_iter()
+        .map(|item| match item {
+            Item::Trait(mut item_trait) => {
+                let trait_args = match take_item_args(&mut item_trait.attrs) {
+                    Ok(trait_args) => trait_args.unwrap_or_default(),
+                    Err(error) => return Item::Verbatim(error.to_compile_error()),
+                };
+                if let Err(error) = trait_args.validate_for_trait() {
+                    return Item::Verbatim(error.to_compile_error());
+                }
+                Item::Verbatim(handle_item_trait(item_trait, &trait_args))
+            }
+            Item::Impl(mut item_impl) => {
+                let declaration = match item_impl.trait_ {
+                    Some((_, ref path, _)) if path.leading_colon.is_none() && path.segments.len() == 1 => {
+                        declarations
+                            .iter()
+                            .find(|declaration| declaration.ident == path.segments[0].ident)
+                    }
+                    _ => None,
+                };
+                let declaration = match declaration {
+                    Some(declaration) => declaration,
+                    // Other impls are left to their own attribute, if any.
+                    None => return Item::Impl(item_impl),
+                };
+                let impl_args = match take_item_args(&mut item_impl.attrs) {
+                    Ok(impl_args) => impl_args,
+                    Err(error) => return Item::Verbatim(error.to_compile_error()),
+                };
+                if let Some(Err(error)) = impl_args.as_ref().map(RealAsyncTraitArgs::validate_for_impl) {
+                    return Item::Verbatim(error.to_compile_error());
+                }
+                let module_ident = impl_module_ident(Some(impl_count));
+                impl_count += 1;
+
+                Item::Verbatim(handle_item_impl(
+                    item_impl,
+                    impl_args.as_ref().unwrap_or(args),
+                    Some(declaration),
+                    &module_ident,
+                ))
+            }
+            item => item,
+        })
+        .collect();
+
+    if let Some((_, ref mut items)) = item.content {
+        *items = expanded_items;
     }
-    .into()
+    item.into_token_stream()
 }
 
 /// A proc macro that supports using async fn in traits and trait impls. Refer to the top-level
@@ -617,6 +3828,14 @@ pub fn real_async_trait(
     real_async_trait2(args_stream.into(), token_stream.into()).into()
 }
 
+/// The second stage of expanding an impl, invoked by the macro that was generated alongside the
+/// trait. Not public API.
+#[doc(hidden)]
+#[proc_macro]
+pub fn __real_async_trait_impl(token_stream: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    real_async_trait_impl2(token_stream.into()).into()
+}
+
 /*
 attrs: [
     Attribute {
@@ -647,34 +3866,54 @@ attrs: [
 
 
 
-fn parse_attributes(attrs: &mut Vec<Attribute>) -> HashSet<RealAsyncTraitAttributes>{
+/// Removes the `#[real_async_trait(...)]` attributes of an async method, returning what they ask
+/// for. Unknown attributes are reported at the offending token.
+fn parse_attributes(attrs: &mut Vec<Attribute>) -> syn::Result<HashSet<RealAsyncTraitAttributes>> {
         let is_real_async_attribute = |attr: &Attribute|{
             let p = &attr.path;
             let t = &attr.tokens;
-            return  p.leading_colon == None && 
-                    p.segments.len() == 1 && 
-                    p.segments.first().unwrap().arguments == syn::PathArguments::None &&
+            p.leading_colon.is_none() &&
+                    p.segments.len() == 1 &&
+                    p.segments.first().unwrap().arguments.is_empty() &&
                     p.segments.first().unwrap().ident.eq("real_async_trait") &&
-                    !t.is_empty();
+                    !t.is_empty()
 
         };
-        let attribute_groups_token_stream: Vec<proc_macro2::TokenStream>= attrs.iter().filter(|attr| is_real_async_attribute(*attr)).map(|attr| attr.tokens.to_owned()).collect();
+        let attribute_groups_token_stream: Vec<proc_macro2::TokenStream>= attrs.iter().filter(|attr| is_real_async_attribute(attr)).map(|attr| attr.tokens.to_owned()).collect();
         attrs.retain(|attr| !is_real_async_attribute(attr));
         let mut ret_val: HashSet<RealAsyncTraitAttributes> = HashSet::new();
-        if attribute_groups_token_stream.len() == 0{
-            return ret_val;
+        if attribute_groups_token_stream.is_empty() {
+            return Ok(ret_val);
         }
-        println!("[{}:{}]{:?}",file!(), line!(), attribute_groups_token_stream);
         for group in attribute_groups_token_stream.into_iter(){
             for tok in group.into_iter(){
-                let string_repr = match tok{
-                    proc_macro2::TokenTree::Group(g) => g.stream().to_string(),
-                    proc_macro2::TokenTree::Ident(i) => i.to_string(),
-                    proc_macro2::TokenTree::Punct(p) => panic!("Did not expect punctuation in the attribute, found: {}", p.as_char()),
-                    proc_macro2::TokenTree::Literal(l) => l.to_string(),
+                let toks = match tok{
+                    // Multiple attributes can be listed within the same group, e.g.
+                    // `#[real_async_trait(boxed, Send)]`.
+                    proc_macro2::TokenTree::Group(g) => g
+                        .stream()
+                        .into_iter()
+                        .filter(|tok| !matches!(tok, proc_macro2::TokenTree::Punct(p) if p.as_char() == ','))
+                        .collect(),
+                    proc_macro2::TokenTree::Punct(p) => {
+                        return Err(syn::Error::new(
+                            p.span(),
+                            format!("did not expect punctuation in the attribute, found `{}`", p.as_char()),
+                        ));
+                    }
+                    tok => vec![tok],
                 };
-                ret_val.insert(RealAsyncTraitAttributes::from_str(&string_repr).expect(&format!("Could not parse the attribute token: {}", string_repr)));
+                for tok in toks {
+                    let string_repr = tok.to_string();
+                    let attribute = RealAsyncTraitAttributes::from_str(&string_repr).map_err(|_| {
+                        syn::Error::new(
+                            tok.span(),
+                            format!("unknown attribute `{}` for an async method, expected `Send` or `boxed`", string_repr),
+                        )
+                    })?;
+                    ret_val.insert(attribute);
+                }
             }
         }
-        return ret_val;
+        Ok(ret_val)
 }
\ No newline at end of file
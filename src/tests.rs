@@ -11,15 +11,28 @@ fn correct_trait_output() {
     };
     let expected_output = quote::quote! {
         pub trait RedoxScheme {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn open<'a>(&'a mut self, path: &'a [u8], flags: usize) -> Result<usize, Errno>`, and the future it returns resolves to `Result<usize, Errno>` and is `Send`."]
             fn open<'a>(&'a mut self, path: &'a [u8], flags: usize) -> Self::__real_async_trait_impl_TypeFor_open<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn read<'a>(&'a mut self, fd: usize, buf: &'a mut [u8]) -> Result<usize, Errno>`, and the future it returns resolves to `Result<usize, Errno>`."]
             fn read<'a>(&'a mut self, fd: usize, buf: &'a mut [u8]) -> Self::__real_async_trait_impl_TypeFor_read<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn write<'a>(&'a mut self, fd: usize, buf: &'a [u8]) -> Result<usize, Errno>`, and the future it returns resolves to `Result<usize, Errno>`."]
             fn write<'a>(&'a mut self, fd: usize, buf: &'a [u8]) -> Self::__real_async_trait_impl_TypeFor_write<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn close<'a>(&'a mut self, fd: usize) -> Result<(), Errno>`, and the future it returns resolves to `Result<(), Errno>`."]
             fn close<'a>(&'a mut self, fd: usize) -> Self::__real_async_trait_impl_TypeFor_close<'a>;
 
-            type __real_async_trait_impl_TypeFor_open<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a + ::core::marker::Send;
-            type __real_async_trait_impl_TypeFor_read<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a;
-            type __real_async_trait_impl_TypeFor_write<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a;
-            type __real_async_trait_impl_TypeFor_close<'a>: ::core::future::Future<Output = Result<(), Errno>> + 'a;
+            #[doc(hidden)]
+
+            type __real_async_trait_impl_TypeFor_open<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a + ::core::marker::Send where Self: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_read<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a where Self: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_write<'a>: ::core::future::Future<Output = Result<usize, Errno>> + 'a where Self: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_close<'a>: ::core::future::Future<Output = Result<(), Errno>> + 'a where Self: 'a;
         }
     };
 
@@ -27,14 +40,24 @@ fn correct_trait_output() {
     let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
     // TODO: Any better way to do this?
     let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
-    let actual_output_trait = syn::parse2::<syn::Item>(actual_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
 
     assert_eq!(expected_output_trait, actual_output_trait);
 }
 #[test]
 fn correct_impl_output() {
+    let trait_input = quote::quote! {
+        pub trait RedoxScheme {
+            #[real_async_trait(Send)]
+            async fn open<'a>(&'a mut self, path: &'a [u8], flags: usize) -> Result<usize, Errno>;
+            async fn read<'a>(&'a mut self, fd: usize, buf: &'a mut [u8]) -> Result<usize, Errno>;
+            async fn write<'a>(&'a mut self, fd: usize, buf: &'a [u8]) -> Result<usize, Errno>;
+            async fn close<'a>(&'a mut self, fd: usize) -> Result<(), Errno>;
+        }
+    };
     let input = quote::quote! {
         impl RedoxScheme for MyType {
+            #[real_async_trait(Send)]
             async fn open<'a>(&'a mut self, path: &'a [u8], flags: usize) -> Result<usize, Errno> {
                 Ok(0)
             }
@@ -50,41 +73,2053 @@ fn correct_impl_output() {
         }
     };
     let expected_output = quote::quote! {
-        mod __real_async_trait_impl {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
             use super::*;
             impl RedoxScheme for MyType {
-                #[real_async_trait(Send)]
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_open)]
                 fn open<'a>(&'a mut self, path: &'a [u8], flags: usize) -> Self::__real_async_trait_impl_TypeFor_open<'a> {
-                    async move { Ok(0) }
+                    async move { let path = path; let flags = flags; Ok(0) }
                 }
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_read)]
                 fn read<'a>(&'a mut self, fd: usize, buf: &'a mut [u8]) -> Self::__real_async_trait_impl_TypeFor_read<'a> {
-                    async move { Ok(0) }
+                    async move { let fd = fd; let buf = buf; Ok(0) }
                 }
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_write)]
                 fn write<'a>(&'a mut self, fd: usize, buf: &'a [u8]) -> Self::__real_async_trait_impl_TypeFor_write<'a> {
-                    async move { Ok(0) }
+                    async move { let fd = fd; let buf = buf; Ok(0) }
                 }
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_close)]
                 fn close<'a>(&'a mut self, fd: usize) -> Self::__real_async_trait_impl_TypeFor_close<'a> {
-                    async move { Ok(()) }
+                    async move { let fd = fd; Ok(()) }
                 }
 
-                type __real_async_trait_impl_TypeFor_open<'a> = self::__real_async_trait_impl_ExistentialTypeFor_open<'a>;
-                type __real_async_trait_impl_TypeFor_read<'a> = self::__real_async_trait_impl_ExistentialTypeFor_read<'a>;
-                type __real_async_trait_impl_TypeFor_write<'a> = self::__real_async_trait_impl_ExistentialTypeFor_write<'a>;
-                type __real_async_trait_impl_TypeFor_close<'a> = self::__real_async_trait_impl_ExistentialTypeFor_close<'a>;
+                type __real_async_trait_impl_TypeFor_open<'a> = self::__real_async_trait_impl_ExistentialTypeFor_open<'a> where Self: 'a;
+                type __real_async_trait_impl_TypeFor_read<'a> = self::__real_async_trait_impl_ExistentialTypeFor_read<'a> where Self: 'a;
+                type __real_async_trait_impl_TypeFor_write<'a> = self::__real_async_trait_impl_ExistentialTypeFor_write<'a> where Self: 'a;
+                type __real_async_trait_impl_TypeFor_close<'a> = self::__real_async_trait_impl_ExistentialTypeFor_close<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_open<'a> = impl ::core::future::Future<Output = Result<usize, Errno>> + 'a + ::core::marker::Send;
+            pub type __real_async_trait_impl_ExistentialTypeFor_read<'a> = impl ::core::future::Future<Output = Result<usize, Errno>> + 'a;
+            pub type __real_async_trait_impl_ExistentialTypeFor_write<'a> = impl ::core::future::Future<Output = Result<usize, Errno>> + 'a;
+            pub type __real_async_trait_impl_ExistentialTypeFor_close<'a> = impl ::core::future::Future<Output = Result<(), Errno>> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_RedoxScheme() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("open", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_open<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_open<'static>>()),
+                    ("read", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_read<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_read<'static>>()),
+                    ("write", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_write<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_write<'static>>()),
+                    ("close", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_close<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_close<'static>>())
+                ];
+                FUTURE_SIZES
             }
-            type __real_async_trait_impl_ExistentialTypeFor_open<'a> = impl ::core::future::Future<Output = Result<usize, Errno>> + 'a + ::core::marker::Send;
-            type __real_async_trait_impl_ExistentialTypeFor_read<'a> = impl ::core::future::Future<Output = Result<usize, Errno>> + 'a;
-            type __real_async_trait_impl_ExistentialTypeFor_write<'a> = impl ::core::future::Future<Output = Result<usize, Errno>> + 'a;
-            type __real_async_trait_impl_ExistentialTypeFor_close<'a> = impl ::core::future::Future<Output = Result<(), Errno>> + 'a;
         }
     };
-    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
     
     // TODO: Any better way to do this?
     let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
-    let actual_output_trait = syn::parse2::<syn::Item>(actual_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
 
     assert_eq!(expected_output_trait, actual_output_trait);
 }
+#[test]
+fn correct_receiver_output() {
+    let trait_input = quote::quote! {
+        pub trait Session {
+            async fn peek<'a>(&'a self) -> u8;
+            async fn poll_once<'a>(self: Pin<&'a mut Self>) -> bool;
+            async fn finish(self) -> Report;
+            async fn finish_boxed<'a>(self: Box<Self>, log: &'a mut Vec<u8>) -> Report;
+            async fn share(self: Arc<Self>) -> usize;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Session {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn peek<'a>(&'a self) -> u8`, and the future it returns resolves to `u8`."]
+            fn peek<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_peek<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn poll_once<'a>(self: Pin<&'a mut Self>) -> bool`, and the future it returns resolves to `bool`."]
+            fn poll_once<'a>(self: Pin<&'a mut Self>) -> Self::__real_async_trait_impl_TypeFor_poll_once<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn finish(self) -> Report`, and the future it returns resolves to `Report`."]
+            fn finish(self) -> Self::__real_async_trait_impl_TypeFor_finish;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn finish_boxed<'a>(self: Box<Self>, log: &'a mut Vec<u8>) -> Report`, and the future it returns resolves to `Report`."]
+            fn finish_boxed<'a>(self: Box<Self>, log: &'a mut Vec<u8>) -> Self::__real_async_trait_impl_TypeFor_finish_boxed<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn share(self: Arc<Self>) -> usize`, and the future it returns resolves to `usize`."]
+            fn share(self: Arc<Self>) -> Self::__real_async_trait_impl_TypeFor_share;
 
-// TODO: Expand tests, and add integration tests.
+            #[doc(hidden)]
+
+            type __real_async_trait_impl_TypeFor_peek<'a>: ::core::future::Future<Output = u8> + 'a where Self: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_poll_once<'a>: ::core::future::Future<Output = bool> + 'a where Self: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_finish: ::core::future::Future<Output = Report>;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_finish_boxed<'a>: ::core::future::Future<Output = Report>;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_share: ::core::future::Future<Output = usize>;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    // The impl only sees a declaration with the methods it implements.
+    let trait_input = quote::quote! {
+        pub trait Session {
+            async fn poll_once<'a>(self: Pin<&'a mut Self>) -> bool;
+            async fn finish(self) -> Report;
+        }
+    };
+    let input = quote::quote! {
+        impl Session for MySession {
+            async fn finish(mut self) -> Report {
+                self.flush();
+                self.report
+            }
+            async fn poll_once<'a>(self: Pin<&'a mut Self>) -> bool {
+                true
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Session for MySession {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_finish)]
+                fn finish(mut self) -> Self::__real_async_trait_impl_TypeFor_finish {
+                    async move {
+                        let _ = &self;
+                        self.flush();
+                        self.report
+                    }
+                }
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_poll_once)]
+                fn poll_once<'a>(self: Pin<&'a mut Self>) -> Self::__real_async_trait_impl_TypeFor_poll_once<'a> {
+                    async move {
+                        let _ = &self;
+                        true
+                    }
+                }
+
+                type __real_async_trait_impl_TypeFor_finish = self::__real_async_trait_impl_ExistentialTypeFor_finish;
+                type __real_async_trait_impl_TypeFor_poll_once<'a> = self::__real_async_trait_impl_ExistentialTypeFor_poll_once<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_finish = impl ::core::future::Future<Output = Report>;
+            pub type __real_async_trait_impl_ExistentialTypeFor_poll_once<'a> = impl ::core::future::Future<Output = bool> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Session() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("finish", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_finish>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_finish>()),
+                    ("poll_once", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_poll_once<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_poll_once<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+#[test]
+fn correct_associated_function_output() {
+    let trait_input = quote::quote! {
+        pub trait Connection {
+            type Error;
+            async fn connect<'a>(addr: &'a str) -> Result<Self, Self::Error>;
+            async fn create() -> Self;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Connection {
+            type Error;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn connect<'a>(addr: &'a str) -> Result<Self, Self::Error>`, and the future it returns resolves to `Result<Self, Self::Error>`."]
+            fn connect<'a>(addr: &'a str) -> Self::__real_async_trait_impl_TypeFor_connect<'a>
+            where
+                Self: ::core::marker::Sized;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn create() -> Self`, and the future it returns resolves to `Self`."]
+            fn create() -> Self::__real_async_trait_impl_TypeFor_create
+            where
+                Self: ::core::marker::Sized;
+
+            #[doc(hidden)]
+
+            type __real_async_trait_impl_TypeFor_connect<'a>: ::core::future::Future<Output = Result<Self, Self::Error>> + 'a
+            where
+                Self: ::core::marker::Sized;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_create: ::core::future::Future<Output = Self>
+            where
+                Self: ::core::marker::Sized;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Connection for TcpConnection {
+            type Error = std::io::Error;
+            async fn connect<'a>(addr: &'a str) -> Result<Self, Self::Error> {
+                Self::open(addr)
+            }
+            async fn create() -> Self {
+                TcpConnection::default()
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Connection for TcpConnection {
+                type Error = std::io::Error;
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_connect)]
+                fn connect<'a>(addr: &'a str) -> Self::__real_async_trait_impl_TypeFor_connect<'a> {
+                    async move { let addr = addr; Self::open(addr) }
+                }
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_create)]
+                fn create() -> Self::__real_async_trait_impl_TypeFor_create {
+                    async move { TcpConnection::default() }
+                }
+
+                type __real_async_trait_impl_TypeFor_connect<'a> = self::__real_async_trait_impl_ExistentialTypeFor_connect<'a>;
+                type __real_async_trait_impl_TypeFor_create = self::__real_async_trait_impl_ExistentialTypeFor_create;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_connect<'a> = impl ::core::future::Future<Output = Result<TcpConnection, <TcpConnection as Connection>::Error>> + 'a;
+            pub type __real_async_trait_impl_ExistentialTypeFor_create = impl ::core::future::Future<Output = TcpConnection>;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Connection() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("connect", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_connect<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_connect<'static>>()),
+                    ("create", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_create>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_create>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+#[test]
+fn correct_provided_method_output() {
+    let trait_input = quote::quote! {
+        pub trait Pinger {
+            async fn pong<'a>(&'a self) -> u8;
+            #[real_async_trait(Send)]
+            async fn ping<'a>(&'a self) -> bool {
+                self.pong().await > 0
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Pinger {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn pong<'a>(&'a self) -> u8`, and the future it returns resolves to `u8`."]
+            fn pong<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_pong<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn ping<'a>(&'a self) -> bool`, and the future it returns resolves to `bool` and is `Send`."]
+            fn ping<'a>(&'a self) -> ::core::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output = bool> + 'a + ::core::marker::Send>> {
+                ::std::boxed::Box::pin(async move {
+                    self.pong().await > 0
+                })
+            }
+
+            #[doc(hidden)]
+
+            type __real_async_trait_impl_TypeFor_pong<'a>: ::core::future::Future<Output = u8> + 'a where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Pinger for MyPinger {
+            async fn pong<'a>(&'a self) -> u8 {
+                1
+            }
+            #[real_async_trait(boxed, Send)]
+            async fn ping<'a>(&'a self) -> bool {
+                false
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Pinger for MyPinger {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_pong)]
+                fn pong<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_pong<'a> {
+                    async move { 1 }
+                }
+                fn ping<'a>(&'a self) -> ::core::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output = bool> + 'a + ::core::marker::Send>> {
+                    ::std::boxed::Box::pin(async move { false })
+                }
+
+                type __real_async_trait_impl_TypeFor_pong<'a> = self::__real_async_trait_impl_ExistentialTypeFor_pong<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_pong<'a> = impl ::core::future::Future<Output = u8> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Pinger() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("pong", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_pong<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_pong<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+#[test]
+fn correct_outlives_where_clauses() {
+    let input = quote::quote! {
+        pub trait Store<'s, T> {
+            async fn get<'a>(&'a self, key: &'a T, prefix: &'s str) -> Option<usize>;
+            async fn insert<'a, 'b>(&'a mut self, key: &'b T) -> bool;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Store<'s, T> {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn get<'a>(&'a self, key: &'a T, prefix: &'s str) -> Option<usize>`, and the future it returns resolves to `Option<usize>`."]
+            fn get<'a>(&'a self, key: &'a T, prefix: &'s str) -> Self::__real_async_trait_impl_TypeFor_get<'a>;
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn insert<'a, 'b>(&'a mut self, key: &'b T) -> bool`, and the future it returns resolves to `bool`."]
+            fn insert<'a, 'b>(&'a mut self, key: &'b T) -> Self::__real_async_trait_impl_TypeFor_insert<'a, 'b>;
+
+            #[doc(hidden)]
+
+            type __real_async_trait_impl_TypeFor_get<'a>: ::core::future::Future<Output = Option<usize> > + 's + 'a
+            where
+                Self: 'a,
+                T: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_insert<'a, 'b>: ::core::future::Future<Output = bool> + 's + 'a + 'b
+            where
+                Self: 'a,
+                T: 'b;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+}
+#[test]
+fn correct_forwarded_attributes() {
+    let trait_input = quote::quote! {
+        pub trait Scheme {
+            /// Opens a file.
+            #[cfg(feature = "open")]
+            #[deprecated]
+            async fn open<'a>(&'a self, path: &'a [u8]) -> usize;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Scheme {
+            /// Opens a file.
+            #[cfg(feature = "open")]
+            #[deprecated]
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn open<'a>(&'a self, path: &'a [u8]) -> usize`, and the future it returns resolves to `usize`."]
+            #[allow(deprecated)]
+            fn open<'a>(&'a self, path: &'a [u8]) -> Self::__real_async_trait_impl_TypeFor_open<'a>;
+
+            #[cfg(feature = "open")]
+            #[deprecated]
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_open<'a>: ::core::future::Future<Output = usize> + 'a where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Scheme for MyScheme {
+            /// Always opens file 0.
+            #[cfg(feature = "open")]
+            #[allow(unused_variables)]
+            #[inline]
+            async fn open<'a>(&'a self, path: &'a [u8]) -> usize {
+                0
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Scheme for MyScheme {
+                /// Always opens file 0.
+                #[cfg(feature = "open")]
+                #[allow(unused_variables)]
+                #[inline]
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_open)]
+                fn open<'a>(&'a self, path: &'a [u8]) -> self::__real_async_trait_impl_ExistentialTypeFor_open<'a> {
+                    async move { let path = path; 0 }
+                }
+
+                /// Always opens file 0.
+                #[cfg(feature = "open")]
+                #[allow(unused_variables)]
+                type __real_async_trait_impl_TypeFor_open<'a> = self::__real_async_trait_impl_ExistentialTypeFor_open<'a> where Self: 'a;
+            }
+            /// Always opens file 0.
+            #[cfg(feature = "open")]
+            #[allow(unused_variables)]
+            pub type __real_async_trait_impl_ExistentialTypeFor_open<'a> = impl ::core::future::Future<Output = usize> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Scheme() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    #[cfg(feature = "open")]
+                    #[allow(unused_variables)]
+                    ("open", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_open<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_open<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_signature_documentation() {
+    let input = quote::quote! {
+        pub trait Sink {
+            /// Flushes all buffered data.
+            async fn flush<'a>(&'a mut self);
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Sink {
+            /// Flushes all buffered data.
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn flush<'a>(&'a mut self)`, and the future it returns resolves to `()`."]
+            fn flush<'a>(&'a mut self) -> Self::__real_async_trait_impl_TypeFor_flush<'a>;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_flush<'a>: ::core::future::Future<Output = ()> + 'a where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+}
+
+#[test]
+fn correct_impl_trait_output() {
+    let trait_input = quote::quote! {
+        pub trait Bytes {
+            fn iter<'a>(&'a self) -> impl Iterator<Item = &'a u8> + 'a;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Bytes {
+            fn iter<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_iter<'a>;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_iter<'a>: Iterator<Item = &'a u8> + 'a where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Bytes for MyBytes {
+            fn iter<'a>(&'a self) -> impl Iterator<Item = &'a u8> + 'a {
+                self.0.iter()
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+
+            impl Bytes for MyBytes {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_iter)]
+                fn iter<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_iter<'a> {
+                    self.0.iter()
+                }
+                type __real_async_trait_impl_TypeFor_iter<'a> = self::__real_async_trait_impl_ExistentialTypeFor_iter<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_iter<'a> = impl Iterator<Item = &'a u8> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Bytes() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_nested_impl_trait_output() {
+    let trait_input = quote::quote! {
+        pub trait Store {
+            async fn list<'a>(&'a self) -> Result<impl Iterator<Item = u32>, Errno>;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Store {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn list<'a>(&'a self) -> Result<impl Iterator<Item = u32>, Errno>`, and the future it returns resolves to `Result<impl Iterator<Item = u32>, Errno>`."]
+            fn list<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_list<'a>;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_list<'a>: ::core::future::Future<Output = Result<Self::__real_async_trait_impl_TypeFor_list_0<'a>, Errno>> + 'a where Self: 'a;
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_list_0<'a>: Iterator<Item = u32> where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Store for MyStore {
+            async fn list<'a>(&'a self) -> Result<impl Iterator<Item = u32>, Errno> {
+                Ok(self.0.clone().into_iter())
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+
+            impl Store for MyStore {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_list)]
+                fn list<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_list<'a> {
+                    async move {
+                        Ok(self.0.clone().into_iter())
+                    }
+                }
+                type __real_async_trait_impl_TypeFor_list<'a> = self::__real_async_trait_impl_ExistentialTypeFor_list<'a> where Self: 'a;
+                type __real_async_trait_impl_TypeFor_list_0<'a> = self::__real_async_trait_impl_ExistentialTypeFor_list_0<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_list<'a> = impl ::core::future::Future<Output = Result<self::__real_async_trait_impl_ExistentialTypeFor_list_0<'a>, Errno>> + 'a;
+            pub type __real_async_trait_impl_ExistentialTypeFor_list_0<'a> = impl Iterator<Item = u32>;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Store() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("list", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_list<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_list<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_boxed_method_output() {
+    let trait_input = quote::quote! {
+        pub trait Tree {
+            #[real_async_trait(boxed, Send)]
+            async fn sum<'a>(&'a self) -> u32;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Tree {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn sum<'a>(&'a self) -> u32`, and the future it returns resolves to `u32` and is `Send`."]
+            fn sum<'a>(&'a self) -> ::core::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output = u32> + 'a + ::core::marker::Send>>;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Tree for Node {
+            #[real_async_trait(boxed, Send)]
+            async fn sum<'a>(&'a self) -> u32 {
+                self.value + self.children.iter().map(|child| child.sum()).len() as u32
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+
+            impl Tree for Node {
+                fn sum<'a>(&'a self) -> ::core::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output = u32> + 'a + ::core::marker::Send>> {
+                    ::std::boxed::Box::pin(async move {
+                        self.value + self.children.iter().map(|child| child.sum()).len() as u32
+                    })
+                }
+            }
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Tree() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_max_future_size_output() {
+    let args = quote::quote! { max_future_size = 2048 };
+    let trait_input = quote::quote! {
+        pub trait Pinger {
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let input = quote::quote! {
+        impl Pinger for MyPinger {
+            async fn ping<'a>(&'a self) -> bool {
+                true
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+
+            impl Pinger for MyPinger {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_ping)]
+                fn ping<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_ping<'a> {
+                    async move { true }
+                }
+                type __real_async_trait_impl_TypeFor_ping<'a> = self::__real_async_trait_impl_ExistentialTypeFor_ping<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_ping<'a> = impl ::core::future::Future<Output = bool> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Pinger() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("ping", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+
+            impl MyPinger {
+                #[doc(hidden)]
+                #[allow(non_snake_case)]
+                pub fn __real_async_trait_impl_FutureSizesFor_Pinger() -> &'static [(&'static str, usize, usize)] {
+                    __real_async_trait_impl_FutureSizesFor_Pinger()
+                }
+            }
+
+            // The arguments of the assertion are compared as tokens, including the spacing of `>`.
+            const _: () = ::core::assert!(
+                ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static> >() <= 2048,
+                "the future returned by `ping` is larger than the `max_future_size` of 2048 bytes"
+            );
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } (#args) #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_variant_output() {
+    let args = quote::quote! { variant(SendPinger: Send) };
+    let input = quote::quote! {
+        pub trait Pinger<T> {
+            async fn ping<'a>(&'a self, payload: T) -> bool;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Pinger<T> {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn ping<'a>(&'a self, payload: T) -> bool`, and the future it returns resolves to `bool`."]
+            fn ping<'a>(&'a self, payload: T) -> Self::__real_async_trait_impl_TypeFor_ping<'a>;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_ping<'a>: ::core::future::Future<Output = bool> + 'a where Self: 'a;
+        }
+
+        #[doc = "A variant of [`Pinger`] whose futures are all bounded by `Send`.\n\nIt is implemented for every implementor of [`Pinger`] for which these bounds hold. Since the bounds have to hold for all lifetimes, only `'static` implementors implement it."]
+        pub trait SendPinger<T>: for<'a> Pinger<T, __real_async_trait_impl_TypeFor_ping<'a>: Send> {}
+
+        impl<T, __RealAsyncTraitImplementor: ?::core::marker::Sized> SendPinger<T> for __RealAsyncTraitImplementor
+        where
+            __RealAsyncTraitImplementor: for<'a> Pinger<T, __real_async_trait_impl_TypeFor_ping<'a>: Send>,
+        {}
+    };
+    let actual_output = crate::real_async_trait2(args.clone(), input);
+    let expected_output_file = syn::parse2::<syn::File>(expected_output).unwrap();
+    let mut actual_output_file = syn::parse2::<syn::File>(actual_output).unwrap();
+    // The macro carrying the trait declaration is checked by `correct_trait_metadata_output`.
+    actual_output_file.items.truncate(expected_output_file.items.len());
+
+    assert_eq!(expected_output_file, actual_output_file);
+
+    // Boxed futures have no associated type to bound, so only `Send` variants work for them, and
+    // only if the futures are `Send` already.
+    let input = quote::quote! {
+        pub trait Pinger {
+            #[real_async_trait(boxed, Send)]
+            async fn ping<'a>(&'a self) -> bool;
+            async fn pong<'a>(&'a self) -> bool {
+                true
+            }
+        }
+    };
+    let expected_error = quote::quote! {
+        compile_error! { "`variant` cannot bound the future of `pong`, which is boxed, unless it is marked `#[real_async_trait(Send)]`" }
+    };
+    let actual_output = crate::real_async_trait2(args, input.clone());
+    assert!(actual_output.to_string().contains(&expected_error.to_string()));
+
+    let expected_error = quote::quote! {
+        compile_error! { "`variant` cannot bound the future of `ping`, which is boxed, by anything but `Send`" }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { variant(SyncPinger: Send + Sync) }, input);
+    assert!(actual_output.to_string().contains(&expected_error.to_string()));
+
+    let input = quote::quote! {
+        pub trait Pinger {
+            #[real_async_trait(boxed, Send)]
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { variant(SendPinger: ::core::marker::Send) }, input);
+    assert!(!actual_output.to_string().contains("compile_error"));
+}
+
+#[test]
+fn correct_identical_trait_metadata_output() {
+    let trait_input = quote::quote! {
+        pub trait Handler {
+            async fn handle<'a>(&'a self, request: &'a [u8]) -> usize;
+        }
+    };
+
+    // Identical traits in two modules, e.g. `v1::Handler` and `v2::Handler`, export macros of
+    // different names, and re-export them under the same one.
+    let metadata_items = (0..2)
+        .map(|_| {
+            let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+            syn::parse2::<syn::File>(actual_output).unwrap().items
+        })
+        .collect::<Vec<_>>();
+    let macro_idents = metadata_items
+        .iter()
+        .map(|items| match items[1] {
+            syn::Item::Macro(syn::ItemMacro { ident: Some(ref ident), .. }) => ident.clone(),
+            ref item => panic!("expected the metadata macro, found {:?}", item),
+        })
+        .collect::<Vec<_>>();
+    assert_ne!(macro_idents[0], macro_idents[1]);
+    for items in metadata_items.iter() {
+        match items[2] {
+            syn::Item::Use(ref item_use) => {
+                assert!(quote::ToTokens::to_token_stream(item_use).to_string().ends_with("as Handler ;"));
+            }
+            ref item => panic!("expected the re-export, found {:?}", item),
+        }
+    }
+}
+
+#[test]
+fn correct_trait_metadata_output() {
+    let trait_input = quote::quote! {
+        pub trait Pinger {
+            #[real_async_trait(Send)]
+            async fn ping<'a>(&'a self) -> bool;
+            async fn pong<'a>(&'a self) -> u8 {
+                0
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let actual_output_items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    // The name of the macro ends with a hash of the declaration, and the number of the expansion.
+    let macro_ident = match actual_output_items[1] {
+        syn::Item::Macro(syn::ItemMacro { ident: Some(ref ident), .. }) => ident.clone(),
+        ref item => panic!("expected the metadata macro, found {:?}", item),
+    };
+    assert!(macro_ident.to_string().starts_with("__real_async_trait_metadata_Pinger_"));
+
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        #[macro_export]
+        macro_rules! #macro_ident {
+            (:: $($path:ident)::+ ! { $($input:tt)* }) => {
+                :: $($path)::+ ! { { #trait_input } $($input)* }
+            };
+        }
+        #[doc(hidden)]
+        pub use #macro_ident as Pinger;
+    };
+    let expected_output_items = syn::parse2::<syn::File>(expected_output).unwrap().items;
+
+    assert_eq!(expected_output_items, actual_output_items[1..]);
+
+    let input = quote::quote! {
+        impl Pinger for MyPinger {
+            async fn ping<'a>(&'a self) -> bool {
+                true
+            }
+            async fn pong<'a>(&'a self) -> u8 {
+                1
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        Pinger! {
+            ::real_async_trait::__real_async_trait_impl! { (max_future_size = 64) #input }
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { max_future_size = 64 }, input.clone());
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+
+    // `Send` and the boxing of the provided method are both taken from the declaration.
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+
+            impl Pinger for MyPinger {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_ping)]
+                fn ping<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_ping<'a> {
+                    async move { true }
+                }
+                fn pong<'a>(&'a self) -> ::core::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output = u8> + 'a>> {
+                    ::std::boxed::Box::pin(async move { 1 })
+                }
+                type __real_async_trait_impl_TypeFor_ping<'a> = self::__real_async_trait_impl_ExistentialTypeFor_ping<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_ping<'a> = impl ::core::future::Future<Output = bool> + 'a + ::core::marker::Send;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Pinger() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("ping", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_signature_mismatch_errors() {
+    let trait_input = quote::quote! {
+        pub trait Store {
+            async fn get<'a>(&'a self, key: &'a str) -> Option<u8>;
+            async fn put<'a>(&'a self, key: &'a str);
+            async fn len<'a>(&'a self) -> usize;
+            async fn this<'a>(&'a self) -> &'a Self;
+            async fn size<'a>(&'a self) -> usize;
+            async fn clear<'a>(&'a self) {}
+        }
+    };
+    // Return types are compared after replacing `Self` and renaming lifetimes, so `this` matches.
+    let input = quote::quote! {
+        impl Store for MyStore {
+            async fn get<'a, 'b>(&'a self, key: &'a str) -> Option<u8> {
+                None
+            }
+            fn put<'a>(&'a self, key: &'a str) {}
+            async fn len<'a>(&'a self) -> u8 {
+                0
+            }
+            async fn this<'b>(&'b self) -> &'b MyStore {
+                self
+            }
+            async fn extra<'a>(&'a self) {}
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`get` has 2 lifetime parameter(s), but its declaration in the trait `Store` has 1" }
+        compile_error! { "`put` is declared as `async fn` in the trait `Store`, but is not async here" }
+        compile_error! { "`len` returns `u8`, but its declaration in the trait `Store` returns `usize`" }
+        compile_error! { "`extra` is not a method of the trait `Store`" }
+        compile_error! { "missing async method(s) of the trait `Store`: `size`" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    // Parameters of the trait are replaced by the arguments of the impl, and projections, which
+    // impls usually write out, are not compared.
+    let trait_input = quote::quote! {
+        pub trait Source<'s, T, E = Error> {
+            type Item;
+            async fn get<'a, K>(&'a self, key: K) -> Result<&'s T, E>;
+            async fn next<'a>(&'a self) -> Option<Self::Item>;
+            async fn last<'a>(&'a self) -> Option<T>;
+        }
+    };
+    let input = quote::quote! {
+        impl<'x> Source<'x, u8> for MySource<'x> {
+            type Item = u16;
+            async fn get<'b, Key>(&'b self, key: Key) -> Result<&'x u8, Error> {
+                Err(Error)
+            }
+            async fn next<'a>(&'a self) -> Option<u16> {
+                None
+            }
+            async fn last<'a>(&'a self) -> Option<u16> {
+                None
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`last` returns `Option<u16>`, but its declaration in the trait `Source` returns `Option<T>`" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+
+#[test]
+fn correct_module_output() {
+    let trait_input = quote::quote! {
+        pub trait Pinger {
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let first_impl = quote::quote! {
+        impl Pinger for First {
+            async fn ping<'a>(&'a self) -> bool {
+                true
+            }
+        }
+    };
+    let second_impl = quote::quote! {
+        impl Pinger for Second {
+            async fn ping<'a>(&'a self) -> bool {
+                false
+            }
+        }
+    };
+    let other_impl = quote::quote! {
+        impl Other for Third {
+            async fn other(&self) {}
+        }
+    };
+    let input = quote::quote! {
+        pub mod pingers {
+            #[real_async_trait]
+            #trait_input
+            #first_impl
+            #[real_async_trait(max_future_size = 64)]
+            #second_impl
+            #other_impl
+        }
+    };
+
+    // The impls are expanded with the declaration from the module, each into its own module.
+    let declaration = syn::parse2(trait_input.clone()).unwrap();
+    let trait_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input);
+    let first_output = crate::handle_item_impl(
+        syn::parse2(first_impl).unwrap(),
+        &syn::parse2(quote::quote! { max_future_size = 16 }).unwrap(),
+        Some(&declaration),
+        &quote::format_ident!("__real_async_trait_impl_0"),
+    );
+    let second_output = crate::handle_item_impl(
+        syn::parse2(second_impl).unwrap(),
+        &syn::parse2(quote::quote! { max_future_size = 64 }).unwrap(),
+        Some(&declaration),
+        &quote::format_ident!("__real_async_trait_impl_1"),
+    );
+    let expected_output = quote::quote! {
+        pub mod pingers {
+            #trait_output
+            #first_output
+            #second_output
+            #other_impl
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { max_future_size = 16 }, input);
+
+    // Each expansion of the trait numbers its metadata macro anew.
+    let without_macro_names = |output: proc_macro2::TokenStream| {
+        output
+            .to_string()
+            .split(' ')
+            .map(|token| if token.starts_with("__real_async_trait_metadata_") { "_" } else { token })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    assert_eq!(without_macro_names(expected_output), without_macro_names(actual_output));
+}
+
+#[test]
+fn correct_named_future_output() {
+    let input = quote::quote! {
+        pub async fn fetch<'a>(url: &'a str) -> Vec<u8> {
+            download(url).await
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc = "The future returned by `fetch`."]
+        pub type FetchFuture<'a> = impl ::core::future::Future<Output = Vec<u8>>;
+
+        #[define_opaque(FetchFuture)]
+        pub fn fetch<'a>(url: &'a str) -> self::FetchFuture<'a> {
+            async move { let url = url; download(url).await }
+        }
+
+        // `>>` would be a single token.
+        const _: () = ::core::assert!(::core::mem::size_of::<FetchFuture<'static> >() <= 128, "the future returned by `fetch` is larger than the `max_future_size` of 128 bytes");
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { max_future_size = 128 }, input);
+    let expected_output_items = syn::parse2::<syn::File>(expected_output).unwrap().items;
+    let actual_output_items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    assert_eq!(expected_output_items, actual_output_items);
+
+    let input = quote::quote! {
+        impl<'c> Client<'c> {
+            pub async fn get<'a>(&'a self, path: &'a str) -> Self {
+                self.clone()
+            }
+            pub fn base(&self) -> &str {
+                self.base
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        impl<'c> Client<'c> {
+            #[define_opaque(GetFuture)]
+            pub fn get<'a>(&'a self, path: &'a str) -> self::GetFuture<'c, 'a> {
+                async move { let path = path; self.clone() }
+            }
+            pub fn base(&self) -> &str {
+                self.base
+            }
+        }
+
+        #[doc = "The future returned by `get`."]
+        pub type GetFuture<'c, 'a> where Client<'c>: 'a = impl ::core::future::Future<Output = Client<'c>>;
+
+        impl<'c> Client<'c> {
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizes() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("get", ::core::mem::size_of::<GetFuture<'static, 'static>>(), ::core::mem::align_of::<GetFuture<'static, 'static>>())
+                ];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    let expected_output_items = syn::parse2::<syn::File>(expected_output).unwrap().items;
+    let actual_output_items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    assert_eq!(expected_output_items, actual_output_items);
+}
+
+#[test]
+fn correct_hidden_lifetime_errors() {
+    let input = quote::quote! {
+        pub trait Store {
+            async fn get<'a>(&'a self, f: &'a mut Formatter<'_>) -> Option<u8>;
+            async fn put<'a>(&'a self, key: impl AsRef<[u8]>, callback: Box<dyn Fn(&u8) -> &u8>, hook: fn(&u8));
+            async fn take(&mut self) -> u8;
+            fn keys<'a>(&'a self) -> impl Iterator<Item = &u8> + 'a;
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`'_` cannot be named by the generated types; use a lifetime parameter of the fn instead" }
+        compile_error! { "&mut self parameter lacked an explicit lifetime, which is required by this proc macro" }
+        compile_error! { "this reference lacks an explicit lifetime, which is required by this proc macro" }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    // Lifetimes elided within paths cannot be told apart from types without lifetimes, so they
+    // pass validation and are left to the compiler.
+    let signature: syn::Signature = syn::parse_quote! {
+        async fn get<'a>(&'a self, key: Cow<str>) -> Option<u8>
+    };
+    assert!(crate::validate_signature(&signature).is_ok());
+}
+
+#[test]
+fn correct_impl_trait_argument_output() {
+    let trait_input = quote::quote! {
+        pub trait Sender {
+            async fn send<'a>(&'a self, body: impl AsRef<[u8]> + 'a) -> usize;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Sender {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn send<'a>(&'a self, body: impl AsRef<[u8]> + 'a) -> usize`, and the future it returns resolves to `usize`."]
+            fn send<'a, __RealAsyncTraitImplArg0: AsRef<[u8]> + 'a>(&'a self, body: __RealAsyncTraitImplArg0) -> Self::__real_async_trait_impl_TypeFor_send<'a, __RealAsyncTraitImplArg0>;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_send<'a, __RealAsyncTraitImplArg0: AsRef<[u8]> + 'a>: ::core::future::Future<Output = usize> + 'a where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Sender for MySender {
+            async fn send<'a>(&'a self, body: impl AsRef<[u8]> + 'a) -> usize {
+                body.as_ref().len()
+            }
+        }
+    };
+    // The size of a future that is generic over types is unknown, so it is not listed.
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Sender for MySender {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_send)]
+                fn send<'a, __RealAsyncTraitImplArg0: AsRef<[u8]> + 'a>(&'a self, body: __RealAsyncTraitImplArg0) -> Self::__real_async_trait_impl_TypeFor_send<'a, __RealAsyncTraitImplArg0> {
+                    async move { let body = body; body.as_ref().len() }
+                }
+
+                type __real_async_trait_impl_TypeFor_send<'a, __RealAsyncTraitImplArg0: AsRef<[u8]> + 'a> = self::__real_async_trait_impl_ExistentialTypeFor_send<'a, __RealAsyncTraitImplArg0> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_send<'a, __RealAsyncTraitImplArg0: AsRef<[u8]> + 'a> = impl ::core::future::Future<Output = usize> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Sender() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_where_clause_output() {
+    let trait_input = quote::quote! {
+        pub trait Store {
+            async fn get<'a, T>(&'a self, fallback: T) -> T where T: Clone + 'a;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Store {
+            #[doc = ""]
+            #[doc = "This method is declared as `async fn get<'a, T>(&'a self, fallback: T) -> T where T: Clone + 'a`, and the future it returns resolves to `T`."]
+            fn get<'a, T>(&'a self, fallback: T) -> Self::__real_async_trait_impl_TypeFor_get<'a, T> where T: Clone + 'a;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_get<'a, T>: ::core::future::Future<Output = T> + 'a where Self: 'a, T: Clone + 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl<V> Store for Wrapper<V> where V: Sync {
+            async fn get<'a, T>(&'a self, fallback: T) -> T where T: Clone + 'a {
+                fallback
+            }
+        }
+    };
+    // The existential type is generic over the parameters of the impl as well, and gets all where
+    // clauses, including `Self: 'a`, which the signature of the method would otherwise imply.
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl<V> Store for Wrapper<V> where V: Sync {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_get)]
+                fn get<'a, T>(&'a self, fallback: T) -> Self::__real_async_trait_impl_TypeFor_get<'a, T> where T: Clone + 'a {
+                    async move { let fallback = fallback; fallback }
+                }
+
+                type __real_async_trait_impl_TypeFor_get<'a, T> = self::__real_async_trait_impl_ExistentialTypeFor_get<'a, V, T> where Self: 'a, T: Clone + 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_get<'a, V, T> where Wrapper<V>: 'a, V: Sync, T: Clone + 'a = impl ::core::future::Future<Output = T> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Store() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_argument_binding_output() {
+    let input = quote::quote! {
+        async fn add(_permit: Permit, (a, _b): (u8, Guard), _: Guard, mut n: u8) -> u8 {
+            n += a;
+            n
+        }
+    };
+    // All arguments are moved into the future, and dropped at the end of it, like those of a
+    // native async fn.
+    let expected_output = quote::quote! {
+        #[doc = "The future returned by `add`."]
+        type AddFuture = impl ::core::future::Future<Output = u8>;
+
+        #[define_opaque(AddFuture)]
+        fn add(_permit: Permit, __real_async_trait_impl_arg1: (u8, Guard), __real_async_trait_impl_arg2: Guard, n: u8) -> self::AddFuture {
+            async move {
+                let _permit = _permit;
+                let __real_async_trait_impl_arg1 = __real_async_trait_impl_arg1;
+                let (a, _b) = __real_async_trait_impl_arg1;
+                let __real_async_trait_impl_arg2 = __real_async_trait_impl_arg2;
+                let _ = __real_async_trait_impl_arg2;
+                let mut n = n;
+                n += a;
+                n
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    let expected_output_items = syn::parse2::<syn::File>(expected_output).unwrap().items;
+    let actual_output_items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    assert_eq!(expected_output_items, actual_output_items);
+
+    // Typed receivers are moved into the future as well, even if the body does not use them.
+    let input = quote::quote! {
+        impl Connection {
+            async fn close(self: Box<Self>, _permit: Permit) -> u8 {
+                0
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    let method = syn::parse2::<syn::File>(actual_output)
+        .unwrap()
+        .items
+        .into_iter()
+        .find_map(|item| match item {
+            syn::Item::Impl(mut item) => match item.items.remove(0) {
+                syn::ImplItem::Method(method) => Some(method),
+                _ => None,
+            },
+            _ => None,
+        })
+        .expect("expected the lowered method");
+    let expected_block: syn::Block = syn::parse_quote! {{
+        async move {
+            let _ = &self;
+            let _permit = _permit;
+            0
+        }
+    }};
+    assert_eq!(expected_block, method.block);
+}
+
+// TODO: Expand tests, and add integration tests.
+
+#[test]
+fn correct_qualifier_output() {
+    let trait_input = quote::quote! {
+        pub trait Device {
+            async unsafe fn read<'a>(&'a self, ptr: *const u8) -> u8;
+        }
+    };
+    let expected_output = quote::quote! {
+        pub trait Device {
+            #[doc = ""]
+            #[doc = "This method is declared as `async unsafe fn read<'a>(&'a self, ptr: *const u8) -> u8`, and the future it returns resolves to `u8`."]
+            unsafe fn read<'a>(&'a self, ptr: *const u8) -> Self::__real_async_trait_impl_TypeFor_read<'a>;
+
+            #[doc(hidden)]
+            type __real_async_trait_impl_TypeFor_read<'a>: ::core::future::Future<Output = u8> + 'a where Self: 'a;
+        }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), trait_input.clone());
+    let expected_output_trait = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_trait = syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0);
+
+    assert_eq!(expected_output_trait, actual_output_trait);
+
+    let input = quote::quote! {
+        impl Device for Mmio {
+            async fn read<'a>(&'a self, ptr: *const u8) -> u8 {
+                0
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`read` is declared as `unsafe` in the trait `Device`, but is not unsafe here" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    let input = quote::quote! {
+        pub trait Device {
+            const async fn id<'a>(&'a self) -> u8;
+            async extern "C" fn reset<'a>(&'a self);
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`id` cannot be both `const` and `async`" }
+        compile_error! { "`reset` cannot have an explicit ABI, which is not supported for async fns by this proc macro" }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+
+#[test]
+fn correct_output_spans() {
+    use std::str::FromStr;
+    use syn::spanned::Spanned;
+
+    let trait_input = proc_macro2::TokenStream::from_str(
+        "pub trait Greeter {
+            async fn greet<'a>(&'a self, name: &'a str) -> String;
+        }",
+    )
+    .unwrap();
+    let input = proc_macro2::TokenStream::from_str(
+        "impl Greeter for English {
+            async fn greet<'a>(&'a self, name: &'a str)
+                -> String
+            {
+                format!(\"Hello, {}!\", name)
+            }
+        }",
+    )
+    .unwrap();
+    let actual_output =
+        crate::real_async_trait_impl2(quote::quote! { { #trait_input } (max_future_size = 64) #input });
+    let module = match syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0) {
+        syn::Item::Mod(module) => module,
+        _ => panic!("expected the hidden module"),
+    };
+    let items = module.content.unwrap().1;
+
+    // Errors about the future as a whole point at the body, and errors about the type it resolves
+    // to at the output type.
+    let method = match items.iter().find_map(|item| match item {
+        syn::Item::Impl(item) => item.items.first(),
+        _ => None,
+    }) {
+        Some(syn::ImplItem::Method(method)) => method,
+        _ => panic!("expected the lowered method"),
+    };
+    match method.block.stmts[..] {
+        [syn::Stmt::Expr(syn::Expr::Async(ref future))] => {
+            assert_eq!(future.async_token.span.start().line, 4);
+        }
+        _ => panic!("expected an async block"),
+    }
+    match method.sig.output {
+        syn::ReturnType::Type(ref arrow, ref ty) => {
+            assert_eq!(arrow.spans[0].start().line, 3);
+            assert_eq!(ty.span().start().line, 3);
+        }
+        syn::ReturnType::Default => panic!("expected a return type"),
+    }
+    let existential_type = items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Type(item) => Some(item),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(existential_type.type_token.span.start().line, 3);
+    assert_eq!(existential_type.ident.span().start().line, 2);
+
+    // A future that is too large is reported at the method, rather than at the attribute.
+    let size_check = items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Const(item) => Some(item),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(size_check.const_token.span.start().line, 2);
+    assert_eq!(size_check.expr.span().start().line, 2);
+}
+
+#[test]
+fn correct_foreign_self_type_output() {
+    let trait_input = quote::quote! {
+        pub trait Named {
+            async fn name<'a>(&'a self) -> &'a str;
+        }
+    };
+    let input = quote::quote! {
+        impl Named for String {
+            async fn name<'a>(&'a self) -> &'a str {
+                self
+            }
+        }
+    };
+
+    // The sizes of the futures are listed within the module, rather than by an inherent impl,
+    // which is not allowed for foreign types.
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    let items = match syn::parse2::<syn::File>(actual_output).unwrap().items.remove(0) {
+        syn::Item::Mod(module) => module.content.unwrap().1,
+        _ => panic!("expected the hidden module"),
+    };
+    let impls = items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Impl(item) => Some(item),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(impls.len(), 1);
+    assert!(impls[0].trait_.is_some());
+    assert!(items.iter().any(|item| matches!(
+        item,
+        syn::Item::Fn(item) if item.sig.ident == "__real_async_trait_impl_FutureSizesFor_Named"
+    )));
+
+    // Given `max_future_size`, the sizes are still checked, but not listed by the type as well.
+    let input = quote::quote! {
+        impl Named for std::string::String {
+            async fn name<'a>(&'a self) -> &'a str {
+                self
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Named for std::string::String {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_name)]
+                fn name<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_name<'a> {
+                    async move { self }
+                }
+
+                type __real_async_trait_impl_TypeFor_name<'a> = self::__real_async_trait_impl_ExistentialTypeFor_name<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_name<'a> = impl ::core::future::Future<Output = &'a str> + 'a;
+
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Named() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[
+                    ("name", ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_name<'static>>(), ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_name<'static>>())
+                ];
+                FUTURE_SIZES
+            }
+
+            const _: () = ::core::assert!(
+                ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_name<'static> >() <= 64,
+                "the future returned by `name` is larger than the `max_future_size` of 64 bytes"
+            );
+        }
+    };
+    let actual_output =
+        crate::real_async_trait_impl2(quote::quote! { { #trait_input } (max_future_size = 64) #input });
+    let expected_output_impl = syn::parse2::<syn::Item>(expected_output).unwrap();
+    let actual_output_impl = syn::parse2::<syn::Item>(actual_output).unwrap();
+    assert_eq!(expected_output_impl, actual_output_impl);
+}
+
+#[test]
+fn correct_method_attribute_output() {
+    let trait_input = quote::quote! {
+        pub trait Greeter {
+            async fn greet<'a>(&'a self, name: &'a str) -> String;
+        }
+    };
+    let input = quote::quote! {
+        impl Greeter for English {
+            #[tracing::instrument(skip(self))]
+            #[cfg_attr(feature = "metrics", timed)]
+            #[inline]
+            async fn greet<'a>(&'a self, name: &'a str) -> String {
+                format!("Hello, {}!", name)
+            }
+        }
+    };
+    // The attribute macros see the helper, which is still an `async fn`.
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Greeter for English {
+                #[inline]
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_greet)]
+                fn greet<'a>(&'a self, name: &'a str) -> Self::__real_async_trait_impl_TypeFor_greet<'a> {
+                    async move {
+                        let name = name;
+                        Self::__real_async_trait_impl_Greeter_greet(self, name).await
+                    }
+                }
+                type __real_async_trait_impl_TypeFor_greet<'a> = self::__real_async_trait_impl_ExistentialTypeFor_greet<'a> where Self: 'a;
+            }
+            impl English {
+                #[allow(non_snake_case)]
+                #[tracing::instrument(skip(self), name = "greet")]
+                #[cfg_attr(feature = "metrics", timed)]
+                async fn __real_async_trait_impl_Greeter_greet<'a>(&'a self, name: &'a str) -> String {
+                    format!("Hello, {}!", name)
+                }
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_greet<'a> = impl ::core::future::Future<Output = String> + 'a;
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Greeter() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[(
+                    "greet",
+                    ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_greet<'static> >(),
+                    ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_greet<'static> >()
+                )];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(
+        quote::quote! { { #trait_input } (method_attributes(timed)) #input },
+    );
+
+    assert_eq!(
+        syn::parse2::<syn::File>(expected_output).unwrap(),
+        syn::parse2::<syn::File>(actual_output).unwrap(),
+    );
+
+    let trait_input = quote::quote! {
+        pub trait Parser<T> {
+            async fn parse<'a>(&'a self, input: &'a str) -> T;
+        }
+    };
+    let input = quote::quote! {
+        impl<T: FromStr> Parser<T> for StrParser {
+            #[instrument]
+            async fn parse<'a>(&'a self, input: &'a str) -> T {
+                input.parse().ok().unwrap()
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "attribute macros on async methods are only supported in impls whose parameters are all used by the implementing type" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    let input = quote::quote! {
+        impl<T: FromStr> Parser<T> for String {
+            #[instrument]
+            async fn parse<'a>(&'a self, input: &'a str) -> T {
+                input.parse().ok().unwrap()
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "attribute macros on async methods are not supported in impls for `String`, since their helpers need an inherent impl, which types from other crates cannot have" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+
+#[test]
+fn correct_instrument_output() {
+    let trait_input = quote::quote! {
+        pub trait Pinger {
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let actual_output = crate::real_async_trait2(
+        quote::quote! { instrument(crate::hooks::SchemeHooks) },
+        trait_input,
+    );
+
+    // Impls in other crates have to find the hooks in the crate of the trait.
+    assert!(actual_output
+        .to_string()
+        .contains("{ # [real_async_trait (instrument ($ crate :: hooks :: SchemeHooks))] pub trait Pinger {"));
+
+    let trait_input = quote::quote! {
+        #[real_async_trait(instrument(crate::hooks::SchemeHooks))]
+        pub trait Pinger {
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let input = quote::quote! {
+        impl Pinger for Server {
+            async fn ping<'a>(&'a self) -> bool {
+                true
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        #[doc(hidden)]
+        pub mod __real_async_trait_impl {
+            use super::*;
+            impl Pinger for Server {
+                #[define_opaque(__real_async_trait_impl_ExistentialTypeFor_ping)]
+                fn ping<'a>(&'a self) -> Self::__real_async_trait_impl_TypeFor_ping<'a> {
+                    async move {
+                        let mut future = ::core::pin::pin!(async move { true });
+                        ::core::future::poll_fn(move |cx| {
+                            <crate::hooks::SchemeHooks>::enter("ping", ::core::option::Option::Some("Pinger"), ::core::any::type_name::<Self>());
+                            let poll = ::core::future::Future::poll(future.as_mut(), cx);
+                            <crate::hooks::SchemeHooks>::exit("ping", ::core::option::Option::Some("Pinger"), ::core::any::type_name::<Self>());
+                            if poll.is_ready() {
+                                <crate::hooks::SchemeHooks>::complete("ping", ::core::option::Option::Some("Pinger"), ::core::any::type_name::<Self>());
+                            }
+                            poll
+                        })
+                        .await
+                    }
+                }
+                type __real_async_trait_impl_TypeFor_ping<'a> = self::__real_async_trait_impl_ExistentialTypeFor_ping<'a> where Self: 'a;
+            }
+            pub type __real_async_trait_impl_ExistentialTypeFor_ping<'a> = impl ::core::future::Future<Output = bool> + 'a;
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_FutureSizesFor_Pinger() -> &'static [(&'static str, usize, usize)] {
+                const FUTURE_SIZES: &[(&str, usize, usize)] = &[(
+                    "ping",
+                    ::core::mem::size_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static> >(),
+                    ::core::mem::align_of::<__real_async_trait_impl_ExistentialTypeFor_ping<'static> >()
+                )];
+                FUTURE_SIZES
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+
+    assert_eq!(
+        syn::parse2::<syn::File>(expected_output).unwrap(),
+        syn::parse2::<syn::File>(actual_output).unwrap(),
+    );
+}
+
+#[test]
+fn correct_metrics_output() {
+    let input = quote::quote! {
+        impl Server {
+            pub async fn ping<'a>(&'a self) -> bool {
+                true
+            }
+            pub async fn pong<'a>(&'a self) -> bool {
+                false
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { metrics }, input);
+    let mut items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    let expected_output = quote::quote! {
+        impl Server {
+            #[doc = "The metrics of the async methods of this impl, as one entry per method."]
+            #[doc = ""]
+            #[doc = "Each entry holds the name of the method, how often its futures were polled, how many of them completed, how many were dropped before completing, and the total number of nanoseconds from their first poll until completion."]
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            pub fn __real_async_trait_impl_Metrics() -> &'static [(
+                &'static str,
+                ::core::sync::atomic::AtomicU64,
+                ::core::sync::atomic::AtomicU64,
+                ::core::sync::atomic::AtomicU64,
+                ::core::sync::atomic::AtomicU64,
+            )] {
+                static METRICS: [(
+                    &str,
+                    ::core::sync::atomic::AtomicU64,
+                    ::core::sync::atomic::AtomicU64,
+                    ::core::sync::atomic::AtomicU64,
+                    ::core::sync::atomic::AtomicU64,
+                ); 2usize] = [
+                    (
+                        "ping",
+                        ::core::sync::atomic::AtomicU64::new(0),
+                        ::core::sync::atomic::AtomicU64::new(0),
+                        ::core::sync::atomic::AtomicU64::new(0),
+                        ::core::sync::atomic::AtomicU64::new(0),
+                    ),
+                    (
+                        "pong",
+                        ::core::sync::atomic::AtomicU64::new(0),
+                        ::core::sync::atomic::AtomicU64::new(0),
+                        ::core::sync::atomic::AtomicU64::new(0),
+                        ::core::sync::atomic::AtomicU64::new(0),
+                    )
+                ];
+                &METRICS
+            }
+        }
+    };
+    assert_eq!(syn::parse2::<syn::Item>(expected_output).unwrap(), items.pop().unwrap());
+
+    // Each method records into its own entry, starting as soon as its future is created.
+    let methods = match items.remove(0) {
+        syn::Item::Impl(item) => item.items,
+        _ => panic!("expected the impl"),
+    };
+    for (index, method) in methods.iter().enumerate() {
+        let body = match method {
+            syn::ImplItem::Method(method) => quote::ToTokens::to_token_stream(&method.block).to_string(),
+            _ => panic!("expected a method"),
+        };
+        let entry = format!("entry : & Self :: __real_async_trait_impl_Metrics () [{}usize]", index);
+        assert!(body.contains(&entry));
+        assert!(body.find(&entry) < body.find("async move"));
+    }
+
+    let trait_input = quote::quote! {
+        pub trait Named {
+            async fn name<'a>(&'a self) -> &'a str;
+        }
+    };
+    let input = quote::quote! {
+        impl Named for String {
+            async fn name<'a>(&'a self) -> &'a str {
+                self.as_str()
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`metrics` is not supported in impls for `String`, since the metrics are returned by an inherent associated function, which types from other crates cannot have" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } (metrics) #input });
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+#[test]
+fn correct_interceptor_output() {
+    let input = quote::quote! {
+        pub trait Store {
+            async fn get<'a>(&'a self, key: &'a str) -> Option<u8>;
+            fn len(&self) -> usize;
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { interceptor }, input);
+    let items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    let expected_struct = quote::quote! {
+        #[doc = "Implements `Store` by delegating to `inner`, and by telling `hooks` about every call of a method with a receiver, before and after it."]
+        pub struct Intercepted<T, H> {
+            pub inner: T,
+            pub hooks: H,
+        }
+    };
+    assert!(items.contains(&syn::parse2(expected_struct).unwrap()));
+
+    let impl_items = items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Mod(item_mod) if item_mod.ident == "__real_async_trait_impl_intercepted" => {
+                match item_mod.content.as_ref().unwrap().1[1] {
+                    syn::Item::Impl(ref item_impl) => Some(item_impl.items.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .expect("expected the impl of the interceptor");
+
+    // Methods delegate to the inner implementation, with the hooks called around them.
+    let expected_len = quote::quote! {
+        fn len(&self) -> usize {
+            use super::__real_async_trait_impl_DebugFor_Intercepted::{ViaDebug as _, ViaOpaque as _};
+            self.hooks.before("len", &[]);
+            let __real_async_trait_impl_output = <__RealAsyncTraitImplInner as Store>::len(&self.inner);
+            self.hooks.after(
+                "len",
+                (&&super::__real_async_trait_impl_DebugFor_Intercepted::DebugArg(&__real_async_trait_impl_output)).debug_arg(),
+            );
+            __real_async_trait_impl_output
+        }
+    };
+    assert_eq!(syn::parse2::<syn::ImplItem>(expected_len).unwrap(), impl_items[1]);
+
+    // Async methods do the same within the future, which is not boxed.
+    let get = match impl_items[0] {
+        syn::ImplItem::Method(ref method) => method,
+        _ => panic!("expected a method"),
+    };
+    assert_eq!(
+        quote::ToTokens::to_token_stream(&get.sig.output).to_string(),
+        "-> Self :: __real_async_trait_impl_TypeFor_get < 'a >",
+    );
+    let body = quote::ToTokens::to_token_stream(&get.block).to_string();
+    let before = body.find("self . hooks . before (\"get\" , & [(& & super :: __real_async_trait_impl_DebugFor_Intercepted :: DebugArg (& key)) . debug_arg ()])");
+    let call = body.find("< __RealAsyncTraitImplInner as Store > :: get (& self . inner , key) . await");
+    let after = body.find("self . hooks . after (\"get\"");
+    assert!(body.starts_with("{ async move"));
+    assert!(before.is_some() && before < call && call < after);
+}
+
+#[test]
+fn correct_send_interceptor_output() {
+    let input = quote::quote! {
+        pub trait Store {
+            #[real_async_trait(Send)]
+            async fn get<'a>(&'a self, key: &'a str) -> Option<u8>;
+            #[real_async_trait(Send)]
+            async fn set<'a>(&'a mut self, key: &'a str, value: u8);
+            async fn len<'a>(&'a self) -> usize;
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { interceptor }, input);
+    let items = syn::parse2::<syn::File>(actual_output).unwrap().items;
+
+    let item_impl = items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Mod(item_mod) if item_mod.ident == "__real_async_trait_impl_intercepted" => {
+                match item_mod.content.as_ref().unwrap().1[1] {
+                    syn::Item::Impl(ref item_impl) => Some(item_impl),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .expect("expected the impl of the interceptor");
+
+    // The futures of `Send` methods hold on to the wrapper, which has to be `Sync` for `&self`,
+    // and `Send` for `&mut self`.
+    let expected_where_clause: syn::WhereClause = syn::parse_quote! {
+        where
+            __RealAsyncTraitImplInner: ::core::marker::Send,
+            __RealAsyncTraitImplHooks: ::core::marker::Send,
+            __RealAsyncTraitImplInner: ::core::marker::Sync,
+            __RealAsyncTraitImplHooks: ::core::marker::Sync
+    };
+    assert_eq!(item_impl.generics.where_clause, Some(expected_where_clause));
+}
+
+#[test]
+fn correct_module_interceptor_output() {
+    let input = quote::quote! {
+        mod scheme {
+            pub trait Store {
+                async fn get<'a>(&'a self, key: &'a str) -> Option<u8>;
+            }
+        }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { interceptor }, input).to_string();
+    assert!(actual_output.starts_with("compile_error !"));
+    assert!(actual_output.contains("`interceptor` can only be given to traits"));
+}
+
+#[test]
+fn correct_misplaced_argument_errors() {
+    use std::str::FromStr;
+
+    let input = quote::quote! {
+        pub trait Pinger {
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let args = proc_macro2::TokenStream::from_str("variant(SendPinger: Send),\n max_future_size = 64").unwrap();
+    let actual_output = crate::real_async_trait2(args, input.clone());
+
+    // The error points at the argument, rather than at the attribute as a whole.
+    let expected_output = quote::quote! {
+        compile_error! { "`max_future_size` can only be given to impl blocks, which define the futures" }
+    };
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+    assert_eq!(actual_output.into_iter().next().unwrap().span().start().line, 2);
+
+    let args = proc_macro2::TokenStream::from_str("variant(SendPinger: Send),\n metrics").unwrap();
+    let actual_output = crate::real_async_trait2(args, input);
+    let expected_output = quote::quote! {
+        compile_error! { "`metrics` can only be given to impl blocks, which define the futures" }
+    };
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    let input = quote::quote! {
+        pub trait Sender {
+            async fn send<'a>(&'a self, body: impl AsRef<[u8]> + 'a) -> usize;
+            fn keys<'a>(&'a self) -> impl Iterator<Item = u8> + 'a {
+                None.into_iter()
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`keys` returns `impl Trait` and has a default body, which is not supported, since implementors cannot name its return type" }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    let input = quote::quote! {
+        impl Sender for Client {
+            async fn send<'a>(&'a self, body: impl AsRef<[u8]> + 'a) -> usize {
+                0
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "`variant` can only be given to traits, since it generates a subtrait" }
+    };
+    let actual_output = crate::real_async_trait2(quote::quote! { variant(SendSender: Send) }, input);
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+
+#[test]
+fn correct_attribute_parse_errors() {
+    let input = quote::quote! {
+        pub trait Pinger {
+            #[real_async_trait(Sned)]
+            async fn ping<'a>(&'a self) -> bool;
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "unknown attribute `Sned` for an async method, expected `Send` or `boxed`" }
+    };
+    let actual_output = crate::real_async_trait2(proc_macro2::TokenStream::new(), input);
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+
+    let trait_input = quote::quote! {
+        pub trait Pinger {
+            async fn ping<'a>(&'a self) -> bool;
+            async fn pong<'a>(&'a self) -> bool;
+        }
+    };
+    let input = quote::quote! {
+        impl Pinger for Client {
+            #[real_async_trait(boxed, Send)]
+            async fn ping<'a>(&'a self) -> bool {
+                true
+            }
+
+            #[real_async_trait = boxed]
+            async fn pong<'a>(&'a self) -> bool {
+                true
+            }
+        }
+    };
+    let expected_output = quote::quote! {
+        compile_error! { "did not expect punctuation in the attribute, found `=`" }
+    };
+    let actual_output = crate::real_async_trait_impl2(quote::quote! { { #trait_input } () #input });
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+
+#[test]
+fn correct_instrument_span_names() {
+    // Spans are named after the method rather than after its helper, unless they are named already.
+    let method_ident = quote::format_ident!("greet");
+    let cases = [
+        (
+            quote::quote! { #[instrument] },
+            quote::quote! { #[instrument(name = "greet")] },
+        ),
+        (
+            quote::quote! { #[tracing::instrument(skip(self),)] },
+            quote::quote! { #[tracing::instrument(skip(self), name = "greet")] },
+        ),
+        (
+            quote::quote! { #[instrument(level = "debug", name = "hello")] },
+            quote::quote! { #[instrument(level = "debug", name = "hello")] },
+        ),
+        (
+            quote::quote! { #[cfg_attr(feature = "tracing", inline, tracing::instrument(skip(self)))] },
+            quote::quote! { #[cfg_attr(feature = "tracing", inline, tracing::instrument(skip(self), name = "greet"))] },
+        ),
+        (
+            quote::quote! { #[cfg_attr(feature = "metrics", timed)] },
+            quote::quote! { #[cfg_attr(feature = "metrics", timed)] },
+        ),
+    ];
+    for (input, expected_output) in cases.iter() {
+        let mut attr = syn::parse::Parser::parse2(syn::Attribute::parse_outer, input.clone()).unwrap().remove(0);
+        crate::name_instrument_span(&mut attr, &method_ident);
+        assert_eq!(expected_output.to_string(), quote::ToTokens::to_token_stream(&attr).to_string());
+    }
+}